@@ -0,0 +1,171 @@
+//! System program durable nonce accounts
+//!
+//! Nonce accounts are bincode-encoded `nonce::state::Versions`. The layout is
+//! fixed, so it is decoded by hand rather than pulling bincode into the
+//! program:
+//!
+//! ```text
+//! 0..4    Versions tag (u32, 0 = Legacy, 1 = Current)
+//! 4..8    State tag    (u32, 0 = Uninitialized, 1 = Initialized)
+//! 8..40   authority
+//! 40..72  durable nonce
+//! 72..80  lamports per signature
+//! ```
+//!
+//! A durable transaction advances its nonce with an `AdvanceNonceAccount`
+//! instruction at index 0, which the runtime processes before any other
+//! instruction. A nonce can only advance once per blockhash, so settlement
+//! must not advance it again when the transaction already did.
+
+use solana_program::{instruction::Instruction, pubkey::Pubkey, system_program};
+
+use crate::error::TossError;
+
+/// Serialized size of a system nonce account
+pub const NONCE_ACCOUNT_LEN: usize = 80;
+
+const VERSION_CURRENT: u32 = 1;
+const STATE_UNINITIALIZED: u32 = 0;
+const STATE_INITIALIZED: u32 = 1;
+
+/// Bincode tag of `SystemInstruction::AdvanceNonceAccount`
+const ADVANCE_NONCE_ACCOUNT_TAG: [u8; 4] = 4u32.to_le_bytes();
+
+/// Initialized state of a durable nonce account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceAccountState {
+    /// Key that must sign transactions and advances using this nonce
+    pub authority: Pubkey,
+    /// Value a durable transaction uses as its recent blockhash
+    pub durable_nonce: [u8; 32],
+    pub lamports_per_signature: u64,
+}
+
+/// Decode an initialized, current-version nonce account.
+///
+/// Legacy nonce accounts cannot back durable transactions and are rejected
+/// along with anything that is not a nonce account at all.
+pub fn unpack_nonce_account(data: &[u8]) -> Result<NonceAccountState, TossError> {
+    if data.len() != NONCE_ACCOUNT_LEN {
+        return Err(TossError::InvalidNonceAccount);
+    }
+
+    let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    if u32_at(0) != VERSION_CURRENT {
+        return Err(TossError::InvalidNonceAccount);
+    }
+    match u32_at(4) {
+        STATE_INITIALIZED => {}
+        STATE_UNINITIALIZED => return Err(TossError::NonceAccountUninitialized),
+        _ => return Err(TossError::InvalidNonceAccount),
+    }
+
+    Ok(NonceAccountState {
+        authority: Pubkey::new_from_array(data[8..40].try_into().unwrap()),
+        durable_nonce: data[40..72].try_into().unwrap(),
+        lamports_per_signature: u64::from_le_bytes(data[72..80].try_into().unwrap()),
+    })
+}
+
+/// Whether `ix` advances `nonce_account` under `authority`, as the leading
+/// instruction of a durable transaction does
+pub fn is_advance_nonce_instruction(
+    ix: &Instruction,
+    nonce_account: &Pubkey,
+    authority: &Pubkey,
+) -> bool {
+    // Accounts: 0. nonce account, 1. RecentBlockhashes sysvar, 2. authority
+    ix.program_id == system_program::ID
+        && ix.data == ADVANCE_NONCE_ACCOUNT_TAG
+        && ix.accounts.len() >= 3
+        && ix.accounts[0].pubkey == *nonce_account
+        && ix.accounts[2].pubkey == *authority
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{
+        hash::Hash,
+        nonce::state::{Data, DurableNonce, State, Versions},
+        system_instruction,
+    };
+
+    fn encode(versions: &Versions) -> Vec<u8> {
+        let mut data = bincode::serialize(versions).unwrap();
+        data.resize(NONCE_ACCOUNT_LEN, 0);
+        data
+    }
+
+    #[test]
+    fn test_unpack_initialized_nonce_account() {
+        let authority = Pubkey::new_unique();
+        let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+        let data = encode(&Versions::new(State::Initialized(Data::new(
+            authority,
+            durable_nonce,
+            5000,
+        ))));
+
+        assert_eq!(
+            unpack_nonce_account(&data),
+            Ok(NonceAccountState {
+                authority,
+                durable_nonce: durable_nonce.as_hash().to_bytes(),
+                lamports_per_signature: 5000,
+            })
+        );
+    }
+
+    #[test]
+    fn test_unpack_rejects_uninitialized_and_legacy_accounts() {
+        let uninitialized = encode(&Versions::new(State::Uninitialized));
+        assert_eq!(
+            unpack_nonce_account(&uninitialized),
+            Err(TossError::NonceAccountUninitialized)
+        );
+
+        let legacy = encode(&Versions::Legacy(Box::new(State::Initialized(
+            Data::default(),
+        ))));
+        assert_eq!(
+            unpack_nonce_account(&legacy),
+            Err(TossError::InvalidNonceAccount)
+        );
+
+        assert_eq!(
+            unpack_nonce_account(&[0u8; 48]),
+            Err(TossError::InvalidNonceAccount)
+        );
+    }
+
+    #[test]
+    fn test_detects_advance_nonce_instruction() {
+        let nonce_account = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let advance = system_instruction::advance_nonce_account(&nonce_account, &authority);
+
+        assert!(is_advance_nonce_instruction(
+            &advance,
+            &nonce_account,
+            &authority
+        ));
+        assert!(!is_advance_nonce_instruction(
+            &advance,
+            &Pubkey::new_unique(),
+            &authority
+        ));
+        assert!(!is_advance_nonce_instruction(
+            &advance,
+            &nonce_account,
+            &Pubkey::new_unique()
+        ));
+
+        let transfer = system_instruction::transfer(&nonce_account, &authority, 4);
+        assert!(!is_advance_nonce_instruction(
+            &transfer,
+            &nonce_account,
+            &authority
+        ));
+    }
+}
@@ -0,0 +1,300 @@
+//! Ed25519Program instruction parsing
+//!
+//! The runtime verifies Ed25519Program instructions before any program runs,
+//! so a program can trust a signature once it has confirmed, through the
+//! instructions sysvar, that the Ed25519Program instruction covers exactly
+//! the public key, signature and message it expects.
+//!
+//! Offsets may point into the Ed25519Program instruction's own data or into
+//! the data of the instruction consuming the signature, so a transaction
+//! need not carry the signature, key or signed message twice. References to
+//! any other instruction are rejected.
+
+use solana_program::{ed25519_program, instruction::Instruction, pubkey::Pubkey};
+
+use crate::{error::TossError, signing};
+
+pub const PUBKEY_SERIALIZED_SIZE: usize = 32;
+pub const SIGNATURE_SERIALIZED_SIZE: usize = 64;
+pub const SIGNATURE_OFFSETS_SERIALIZED_SIZE: usize = 14;
+pub const SIGNATURE_OFFSETS_START: usize = 2;
+pub const DATA_START: usize = SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_SERIALIZED_SIZE;
+
+/// Instruction index the Ed25519Program reads as "this instruction's own data"
+pub const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+/// Offset of `intent.from` in a signing preimage: the signing prefix, then
+/// the envelope's version byte, then the intent, which opens with `from`
+pub const SENDER_OFFSET_IN_PREIMAGE: usize = signing::PREFIX_LEN + 1;
+
+/// Offsets of one signature inside an Ed25519Program instruction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ed25519SignatureOffsets {
+    pub signature_offset: u16,
+    pub signature_instruction_index: u16,
+    pub public_key_offset: u16,
+    pub public_key_instruction_index: u16,
+    pub message_data_offset: u16,
+    pub message_data_size: u16,
+    pub message_instruction_index: u16,
+}
+
+impl Ed25519SignatureOffsets {
+    /// Decode offsets from their 14-byte little-endian representation
+    pub fn unpack(data: &[u8]) -> Option<Self> {
+        if data.len() < SIGNATURE_OFFSETS_SERIALIZED_SIZE {
+            return None;
+        }
+        let field = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+        Some(Self {
+            signature_offset: field(0),
+            signature_instruction_index: field(1),
+            public_key_offset: field(2),
+            public_key_instruction_index: field(3),
+            message_data_offset: field(4),
+            message_data_size: field(5),
+            message_instruction_index: field(6),
+        })
+    }
+
+    /// Encode offsets into their 14-byte little-endian representation
+    pub fn pack(&self) -> [u8; SIGNATURE_OFFSETS_SERIALIZED_SIZE] {
+        let mut out = [0u8; SIGNATURE_OFFSETS_SERIALIZED_SIZE];
+        let fields = [
+            self.signature_offset,
+            self.signature_instruction_index,
+            self.public_key_offset,
+            self.public_key_instruction_index,
+            self.message_data_offset,
+            self.message_data_size,
+            self.message_instruction_index,
+        ];
+        for (chunk, field) in out.chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Public key, signature and message covered by one signature of an
+/// Ed25519Program instruction
+#[derive(Debug, PartialEq, Eq)]
+pub struct SignedPayload<'a> {
+    pub public_key: &'a [u8],
+    pub signature: &'a [u8],
+    pub message: &'a [u8],
+}
+
+/// Index and data of the instruction consuming an Ed25519Program
+/// instruction's signatures
+pub type ConsumingInstruction<'a> = (u16, &'a [u8]);
+
+/// Extract the signed payload from an Ed25519Program instruction verifying
+/// exactly one signature.
+///
+/// Offsets may reference the Ed25519Program instruction itself or
+/// `consumer`, if given.
+pub fn parse_single_signature<'a>(
+    instruction: &'a Instruction,
+    consumer: Option<ConsumingInstruction<'a>>,
+) -> Result<SignedPayload<'a>, TossError> {
+    if instruction.program_id != ed25519_program::ID {
+        return Err(TossError::MissingSignatureInstruction);
+    }
+    if instruction.data.len() < DATA_START {
+        return Err(TossError::MalformedSignatureInstruction);
+    }
+    match instruction.data[0] {
+        0 => return Err(TossError::MalformedSignatureInstruction),
+        1 => {}
+        _ => return Err(TossError::MultipleSignatures),
+    }
+    let mut payloads = parse_signatures_referencing(instruction, consumer)?;
+    Ok(payloads.remove(0))
+}
+
+/// Extract every signed payload from a self-contained Ed25519Program
+/// instruction, in signature order
+pub fn parse_signatures(instruction: &Instruction) -> Result<Vec<SignedPayload<'_>>, TossError> {
+    parse_signatures_referencing(instruction, None)
+}
+
+/// Extract every signed payload from an Ed25519Program instruction, in
+/// signature order, resolving offsets into the Ed25519Program instruction
+/// itself or into `consumer`
+pub fn parse_signatures_referencing<'a>(
+    instruction: &'a Instruction,
+    consumer: Option<ConsumingInstruction<'a>>,
+) -> Result<Vec<SignedPayload<'a>>, TossError> {
+    if instruction.program_id != ed25519_program::ID {
+        return Err(TossError::MissingSignatureInstruction);
+    }
+
+    let data = &instruction.data[..];
+    let resolve = |index: u16| match consumer {
+        _ if index == CURRENT_INSTRUCTION => Ok(data),
+        Some((consumer_index, consumer_data)) if index == consumer_index => Ok(consumer_data),
+        _ => Err(TossError::MalformedSignatureInstruction),
+    };
+    let count = *data
+        .first()
+        .ok_or(TossError::MalformedSignatureInstruction)? as usize;
+    (0..count)
+        .map(|i| {
+            let start = SIGNATURE_OFFSETS_START + i * SIGNATURE_OFFSETS_SERIALIZED_SIZE;
+            let offsets = data
+                .get(start..)
+                .and_then(Ed25519SignatureOffsets::unpack)
+                .ok_or(TossError::MalformedSignatureInstruction)?;
+
+            Ok(SignedPayload {
+                public_key: slice_at(
+                    resolve(offsets.public_key_instruction_index)?,
+                    offsets.public_key_offset,
+                    PUBKEY_SERIALIZED_SIZE,
+                )?,
+                signature: slice_at(
+                    resolve(offsets.signature_instruction_index)?,
+                    offsets.signature_offset,
+                    SIGNATURE_SERIALIZED_SIZE,
+                )?,
+                message: slice_at(
+                    resolve(offsets.message_instruction_index)?,
+                    offsets.message_data_offset,
+                    offsets.message_data_size as usize,
+                )?,
+            })
+        })
+        .collect()
+}
+
+fn slice_at(data: &[u8], offset: u16, len: usize) -> Result<&[u8], TossError> {
+    let start = offset as usize;
+    start
+        .checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or(TossError::MalformedSignatureInstruction)
+}
+
+/// Build a self-contained Ed25519Program instruction verifying `signature`
+/// by `public_key` over `message`
+pub fn new_ed25519_instruction(
+    public_key: &Pubkey,
+    signature: &[u8; SIGNATURE_SERIALIZED_SIZE],
+    message: &[u8],
+) -> Instruction {
+    new_ed25519_batch_instruction(&[(public_key, signature, message)])
+}
+
+/// Build a self-contained Ed25519Program instruction verifying each
+/// `(public_key, signature, message)` in order, as `ProcessIntentBatch`
+/// expects
+pub fn new_ed25519_batch_instruction(
+    signatures: &[(&Pubkey, &[u8; SIGNATURE_SERIALIZED_SIZE], &[u8])],
+) -> Instruction {
+    let header_len = SIGNATURE_OFFSETS_START + signatures.len() * SIGNATURE_OFFSETS_SERIALIZED_SIZE;
+    let mut header = Vec::with_capacity(header_len);
+    header.extend_from_slice(&[signatures.len() as u8, 0]);
+    let mut body = Vec::new();
+
+    for (public_key, signature, message) in signatures {
+        let public_key_offset = header_len + body.len();
+        let signature_offset = public_key_offset + PUBKEY_SERIALIZED_SIZE;
+        let message_data_offset = signature_offset + SIGNATURE_SERIALIZED_SIZE;
+        let offsets = Ed25519SignatureOffsets {
+            signature_offset: signature_offset as u16,
+            signature_instruction_index: CURRENT_INSTRUCTION,
+            public_key_offset: public_key_offset as u16,
+            public_key_instruction_index: CURRENT_INSTRUCTION,
+            message_data_offset: message_data_offset as u16,
+            message_data_size: message.len() as u16,
+            message_instruction_index: CURRENT_INSTRUCTION,
+        };
+        header.extend_from_slice(&offsets.pack());
+        body.extend_from_slice(public_key.as_ref());
+        body.extend_from_slice(&signature[..]);
+        body.extend_from_slice(message);
+    }
+
+    header.extend_from_slice(&body);
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data: header,
+    }
+}
+
+/// Build a self-contained Ed25519Program instruction verifying each
+/// `(signature, preimage)` by the sender named in the preimage.
+///
+/// The public key offsets point at `intent.from` inside each signing
+/// preimage instead of repeating the key, saving 32 bytes per intent over
+/// `new_ed25519_batch_instruction`.
+pub fn new_ed25519_intent_batch_instruction(
+    signatures: &[(&[u8; SIGNATURE_SERIALIZED_SIZE], &[u8])],
+) -> Instruction {
+    let header_len = SIGNATURE_OFFSETS_START + signatures.len() * SIGNATURE_OFFSETS_SERIALIZED_SIZE;
+    let mut header = Vec::with_capacity(header_len);
+    header.extend_from_slice(&[signatures.len() as u8, 0]);
+    let mut body = Vec::new();
+
+    for (signature, preimage) in signatures {
+        let signature_offset = header_len + body.len();
+        let message_data_offset = signature_offset + SIGNATURE_SERIALIZED_SIZE;
+        let offsets = Ed25519SignatureOffsets {
+            signature_offset: signature_offset as u16,
+            signature_instruction_index: CURRENT_INSTRUCTION,
+            public_key_offset: (message_data_offset + SENDER_OFFSET_IN_PREIMAGE) as u16,
+            public_key_instruction_index: CURRENT_INSTRUCTION,
+            message_data_offset: message_data_offset as u16,
+            message_data_size: preimage.len() as u16,
+            message_instruction_index: CURRENT_INSTRUCTION,
+        };
+        header.extend_from_slice(&offsets.pack());
+        body.extend_from_slice(&signature[..]);
+        body.extend_from_slice(preimage);
+    }
+
+    header.extend_from_slice(&body);
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data: header,
+    }
+}
+
+/// Build an Ed25519Program instruction carrying nothing but `offsets`, which
+/// reference the signature, public key and message in another instruction
+pub fn new_ed25519_referencing_instruction(offsets: &Ed25519SignatureOffsets) -> Instruction {
+    let mut data = vec![1, 0];
+    data.extend_from_slice(&offsets.pack());
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_count(count: u8) -> Instruction {
+        let mut instruction = new_ed25519_instruction(&Pubkey::new_unique(), &[0; 64], &[0; 32]);
+        instruction.data[0] = count;
+        instruction
+    }
+
+    #[test]
+    fn test_signature_count_must_be_one() {
+        assert!(parse_single_signature(&with_count(1), None).is_ok());
+        assert_eq!(
+            parse_single_signature(&with_count(0), None),
+            Err(TossError::MalformedSignatureInstruction)
+        );
+        assert_eq!(
+            parse_single_signature(&with_count(2), None),
+            Err(TossError::MultipleSignatures)
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! Error types for the TOSS Intent Processor program
+//!
+//! Every `TossError` reaches clients as `ProgramError::Custom(code)`. Codes
+//! are part of the program's interface: they are grouped by the check that
+//! failed, never renumbered, and new variants only take unused codes.
+//!
+//! | Range | Check |
+//! | ----- | ----- |
+//! | 6000-6099 | Ed25519 signature verification and required signers |
+//! | 6100-6199 | Intent decoding and account matching |
+//! | 6200-6299 | Replay protection and settlement receipts |
+//! | 6300-6399 | Durable nonce accounts |
+//! | 6400-6499 | Funding |
+//! | 6500-6599 | SPL Token settlement |
+//! | 6600-6699 | Spending vaults |
+//! | 6700-6799 | Double-spend fraud proofs |
+//! | 6800-6899 | Invoices |
+//! | 6900-6999 | Recurring mandates |
+//! | 7000-7099 | Hash-time-locked escrows |
+
+use num_derive::FromPrimitive;
+use solana_program::{decode_error::DecodeError, program_error::ProgramError};
+use thiserror::Error;
+
+/// Errors returned by the TOSS Intent Processor, surfaced to clients as
+/// `ProgramError::Custom(code)`
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum TossError {
+    /// 6000: The instruction preceding `ProcessIntent` is not an Ed25519Program instruction
+    #[error("Missing Ed25519 signature instruction")]
+    MissingSignatureInstruction = 6000,
+
+    /// 6001: The Ed25519Program instruction verifies more than one signature
+    #[error("Ed25519 instruction must verify exactly one signature")]
+    MultipleSignatures = 6001,
+
+    /// 6002: The Ed25519Program instruction data or offsets could not be parsed
+    #[error("Malformed Ed25519 signature instruction")]
+    MalformedSignatureInstruction = 6002,
+
+    /// 6003: The public key verified by the Ed25519Program is not the intent sender
+    #[error("Ed25519 public key does not match intent sender")]
+    SignerMismatch = 6003,
+
+    /// 6004: The signature verified by the Ed25519Program is not the supplied signature
+    #[error("Ed25519 signature does not match supplied signature")]
+    SignatureMismatch = 6004,
+
+    /// 6005: The message verified by the Ed25519Program is not the intent's signing preimage
+    #[error("Ed25519 message does not match intent preimage")]
+    MessageMismatch = 6005,
+
+    /// 6006: An account that must sign the transaction did not
+    #[error("Missing required signer")]
+    MissingRequiredSigner = 6006,
+
+    /// 6007: The account passed as the instructions sysvar is not the instructions sysvar
+    #[error("Invalid instructions sysvar account")]
+    InvalidInstructionsSysvar = 6007,
+
+    /// 6100: The intent envelope carries a format version this program does not know
+    #[error("Unsupported intent format version")]
+    UnsupportedIntentVersion = 6100,
+
+    /// 6101: The intent payload could not be decoded
+    #[error("Malformed intent data")]
+    MalformedIntent = 6101,
+
+    /// 6102: The sender account is not `intent.from`
+    #[error("Sender account does not match intent")]
+    SenderMismatch = 6102,
+
+    /// 6103: The recipient account is not `intent.to`
+    #[error("Recipient account does not match intent")]
+    RecipientMismatch = 6103,
+
+    /// 6104: The cluster clock is past `intent.expiry`
+    #[error("Intent has expired")]
+    IntentExpired = 6104,
+
+    /// 6105: An intent extension is repeated or inconsistent with the intent
+    #[error("Invalid intent extension")]
+    InvalidIntentExtension = 6105,
+
+    /// 6106: The intent's not-before time or slot has not been reached
+    #[error("Intent is not yet valid")]
+    IntentNotYetValid = 6106,
+
+    /// 6107: The intent's relayer fee names a different relayer than the one settling it
+    #[error("Relayer does not match intent")]
+    RelayerMismatch = 6107,
+
+    /// 6108: The account funding settlement is not the sponsor the intent pins
+    #[error("Settlement sponsor does not match intent")]
+    SponsorMismatch = 6108,
+
+    /// 6109: The reference accounts are not the intent's reference keys, in order,
+    /// passed read-only and unsigned
+    #[error("Reference account does not match intent")]
+    ReferenceMismatch = 6109,
+
+    /// 6200: The nonce tracker account is not the sender's tracker PDA
+    #[error("Invalid nonce tracker account")]
+    InvalidNonceTracker = 6200,
+
+    /// 6201: The intent nonce has already been consumed by a settled intent
+    #[error("Intent nonce already used")]
+    NonceAlreadyUsed = 6201,
+
+    /// 6202: The intent nonce fell below the sender's replay window
+    #[error("Intent nonce is below the replay window")]
+    NonceTooOld = 6202,
+
+    /// 6203: The requested replay window size is zero or exceeds the bitmap size
+    #[error("Invalid nonce window size")]
+    InvalidNonceWindow = 6203,
+
+    /// 6204: The receipt account is not the PDA derived from the intent hash
+    #[error("Invalid settlement receipt account")]
+    InvalidReceiptAccount = 6204,
+
+    /// 6205: A receipt already exists for this intent
+    #[error("Intent already settled")]
+    IntentAlreadySettled = 6205,
+
+    /// 6206: The intent nonce is more than the replay window above the highest
+    /// settled nonce
+    #[error("Intent nonce is too far above the replay window")]
+    NonceTooFarAhead = 6206,
+
+    /// 6300: The durable nonce account is not `intent.nonce_account`
+    #[error("Nonce account does not match intent")]
+    NonceAccountMismatch = 6300,
+
+    /// 6301: The durable nonce authority is not `intent.nonce_auth`
+    #[error("Nonce authority does not match intent")]
+    NonceAuthorityMismatch = 6301,
+
+    /// 6302: The durable nonce account is not a usable system nonce account
+    #[error("Invalid nonce account")]
+    InvalidNonceAccount = 6302,
+
+    /// 6303: The durable nonce account has not been initialized
+    #[error("Nonce account is not initialized")]
+    NonceAccountUninitialized = 6303,
+
+    /// 6304: The authority stored in the nonce account is not `intent.nonce_auth`
+    #[error("Nonce account authority does not match intent")]
+    NonceAccountAuthorityMismatch = 6304,
+
+    /// 6305: The nonce account no longer holds the durable nonce committed in the intent
+    #[error("Durable nonce does not match intent")]
+    DurableNonceMismatch = 6305,
+
+    /// 6306: The intent sets only one of `nonce_account` and `nonce_auth`
+    #[error("Intent must set both or neither of nonce account and authority")]
+    IncompleteNonceConfig = 6306,
+
+    /// 6400: The sender cannot cover the intent amount
+    #[error("Insufficient funds for intent")]
+    InsufficientFunds = 6400,
+
+    /// 6401: The account paying for the recipient's token account cannot cover its rent
+    #[error("Rent payer cannot fund recipient token account")]
+    RentPayerInsufficientFunds = 6401,
+
+    /// 6500: The token program account is neither SPL Token nor Token-2022
+    #[error("Invalid token program")]
+    InvalidTokenProgram = 6500,
+
+    /// 6501: The mint account is not the intent's mint
+    #[error("Mint does not match intent")]
+    TokenMintMismatch = 6501,
+
+    /// 6502: The mint's decimals differ from the decimals the sender signed
+    #[error("Mint decimals do not match intent")]
+    TokenDecimalsMismatch = 6502,
+
+    /// 6503: A token account is not an initialized account for the intent's mint
+    #[error("Invalid token account")]
+    InvalidTokenAccount = 6503,
+
+    /// 6504: A token account is not owned by the intent's sender or recipient
+    #[error("Token account owner does not match intent")]
+    TokenAccountOwnerMismatch = 6504,
+
+    /// 6505: The mint charges a transfer fee and the intent does not accept one
+    #[error("Intent does not accept the mint's transfer fee")]
+    TransferFeeNotAccepted = 6505,
+
+    /// 6506: The mint's transfer fee exceeds the maximum the sender accepted
+    #[error("Transfer fee exceeds intent maximum")]
+    TransferFeeExceedsMax = 6506,
+
+    /// 6507: The memo program account is not SPL Memo
+    #[error("Invalid memo program")]
+    InvalidMemoProgram = 6507,
+
+    /// 6508: The recipient token account to create is not the recipient's associated token account
+    #[error("Recipient token account is not the associated token account")]
+    InvalidAssociatedTokenAccount = 6508,
+
+    /// 6600: The vault account is not an initialized vault PDA of the expected owner
+    #[error("Invalid vault account")]
+    InvalidVault = 6600,
+
+    /// 6601: The signer is not the vault's owner
+    #[error("Signer does not own the vault")]
+    VaultOwnerMismatch = 6601,
+
+    /// 6602: The vault has no pending withdrawal of this mint
+    #[error("No withdrawal requested")]
+    WithdrawalNotRequested = 6602,
+
+    /// 6603: The pending withdrawal's delay has not passed yet
+    #[error("Withdrawal still timelocked")]
+    WithdrawalLocked = 6603,
+
+    /// 6604: Withdrawal delay exceeds `MAX_WITHDRAWAL_DELAY`
+    #[error("Invalid withdrawal delay")]
+    InvalidWithdrawalDelay = 6604,
+
+    /// 6605: The vault holds a bond but no bond release has been requested
+    #[error("Bond release not requested")]
+    BondReleaseNotRequested = 6605,
+
+    /// 6606: The bond release delay has not passed yet
+    #[error("Bond still timelocked")]
+    BondLocked = 6606,
+
+    /// 6700: The reported intents are not two different intents from one sender with one nonce
+    #[error("Intents do not conflict")]
+    IntentsDoNotConflict = 6700,
+
+    /// 6701: The receipt account is not the settlement receipt of the reported intent
+    #[error("Reported intent has not settled")]
+    IntentNotSettled = 6701,
+
+    /// 6702: The victim account is not paid by the conflicting intent
+    #[error("Victim does not match conflicting intent")]
+    VictimMismatch = 6702,
+
+    /// 6703: Fraud record PDA does not match the conflicting intent and victim
+    #[error("Invalid fraud record account")]
+    InvalidFraudRecord = 6703,
+
+    /// 6704: This victim of the conflicting intent has already been compensated
+    #[error("Double spend already reported")]
+    DoubleSpendAlreadyReported = 6704,
+
+    /// 6705: The conflicting intent moves tokens, which the lamport bond does not back
+    #[error("Token intents cannot be slashed")]
+    TokenIntentNotSlashable = 6705,
+
+    /// 6800: The invoice account is not an initialized invoice PDA of the expected payee and id
+    #[error("Invalid invoice account")]
+    InvalidInvoice = 6800,
+
+    /// 6801: Invoice amount is zero, its expiry has passed or its memo is invalid
+    #[error("Invalid invoice terms")]
+    InvalidInvoiceTerms = 6801,
+
+    /// 6802: The invoice has already been paid or was cancelled
+    #[error("Invoice is not open")]
+    InvoiceNotOpen = 6802,
+
+    /// 6803: The cluster clock is past the invoice's expiry
+    #[error("Invoice has expired")]
+    InvoiceExpired = 6803,
+
+    /// 6804: The intent's amount or mint differs from the invoice
+    #[error("Intent does not match invoice")]
+    InvoiceMismatch = 6804,
+
+    /// 6805: An open, unexpired invoice must be cancelled before it is closed
+    #[error("Invoice is still open")]
+    InvoiceStillOpen = 6805,
+
+    /// 6900: The mandate account is not the payer's mandate PDA for the intent,
+    /// or a mandate intent was used as a one-off payment or vice versa
+    #[error("Invalid mandate")]
+    InvalidMandate = 6900,
+
+    /// 6901: The mandate is already active
+    #[error("Mandate already active")]
+    MandateAlreadyActive = 6901,
+
+    /// 6902: The payer revoked the mandate
+    #[error("Mandate revoked")]
+    MandateRevoked = 6902,
+
+    /// 6903: Every period of the mandate has passed
+    #[error("Mandate has ended")]
+    MandateEnded = 6903,
+
+    /// 6904: The pull exceeds what is left of the current period's amount
+    #[error("Mandate period amount exceeded")]
+    MandateAmountExceeded = 6904,
+
+    /// 7000: The escrow account is not the escrow PDA of the intent, or is already in use
+    #[error("Invalid escrow account")]
+    InvalidEscrow = 7000,
+
+    /// 7001: The hash lock's deadline has passed, so funds can no longer be locked or claimed
+    #[error("Hash lock deadline has passed")]
+    HashLockExpired = 7001,
+
+    /// 7002: The revealed preimage does not hash to the committed hash
+    #[error("Preimage does not match hash lock")]
+    PreimageMismatch = 7002,
+
+    /// 7003: The escrow cannot be refunded before its deadline
+    #[error("Escrow still locked")]
+    EscrowStillLocked = 7003,
+}
+
+impl TossError {
+    /// Whether resubmitting the same intent later can succeed.
+    ///
+    /// Retryable errors depend on chain state that may change (balances,
+    /// time); permanent errors mean the intent or its accounts are wrong and
+    /// the same submission will always fail.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            TossError::InsufficientFunds
+                | TossError::RentPayerInsufficientFunds
+                | TossError::IntentNotYetValid
+                | TossError::NonceTooFarAhead
+                | TossError::WithdrawalLocked
+                | TossError::BondLocked
+                | TossError::IntentNotSettled
+                | TossError::MandateAmountExceeded
+                | TossError::EscrowStillLocked
+        )
+    }
+
+    /// Map a `ProgramError::Custom` code back to a `TossError`
+    pub fn from_code(code: u32) -> Option<Self> {
+        num_traits::FromPrimitive::from_u32(code)
+    }
+}
+
+impl From<TossError> for ProgramError {
+    fn from(e: TossError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for TossError {
+    fn type_of() -> &'static str {
+        "TossError"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip() {
+        for code in 6000..8000 {
+            if let Some(error) = TossError::from_code(code) {
+                assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+            }
+        }
+        assert_eq!(
+            TossError::from_code(6201),
+            Some(TossError::NonceAlreadyUsed)
+        );
+        assert_eq!(TossError::from_code(0), None);
+    }
+
+    #[test]
+    fn test_retryable_classification() {
+        assert!(TossError::InsufficientFunds.is_retryable());
+        assert!(TossError::IntentNotYetValid.is_retryable());
+        assert!(TossError::RentPayerInsufficientFunds.is_retryable());
+        assert!(TossError::WithdrawalLocked.is_retryable());
+        assert!(TossError::BondLocked.is_retryable());
+        assert!(TossError::MandateAmountExceeded.is_retryable());
+        assert!(TossError::IntentNotSettled.is_retryable());
+        assert!(TossError::NonceTooFarAhead.is_retryable());
+        assert!(!TossError::IntentExpired.is_retryable());
+        assert!(!TossError::IntentAlreadySettled.is_retryable());
+        assert!(!TossError::SignerMismatch.is_retryable());
+    }
+}
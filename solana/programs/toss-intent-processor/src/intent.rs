@@ -0,0 +1,820 @@
+//! Versioned intent envelope
+//!
+//! `ProcessIntent.intent_data` is a one-byte format version followed by the
+//! Borsh payload for that version. Intents already queued offline keep
+//! settling under their original version while new fields go into new ones.
+//!
+//! Intents serialised before the envelope existed carry no version byte.
+//! Data that is not a valid versioned envelope but decodes exactly as a bare
+//! `SolanaIntent` is accepted as v1. The two readings of the same bytes
+//! disagree on `from` by a one-byte shift, so a blob can only ever pass the
+//! sender signature check under the layout it was signed in.
+//!
+//! Version 2 appends a list of optional `IntentExtension`s to the v1 fields,
+//! so new optional terms are added as extension variants without another
+//! format version.
+
+use std::mem;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{clock::Clock, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{error::TossError, SolanaIntent};
+
+/// Original `SolanaIntent` layout
+pub const INTENT_V1: u8 = 1;
+
+/// `SolanaIntent` followed by `Vec<IntentExtension>`
+pub const INTENT_V2: u8 = 2;
+
+/// Most legs an `IntentExtension::Split` may add to an intent
+pub const MAX_SPLIT_LEGS: usize = 8;
+
+/// Longest `IntentExtension::Memo`, in bytes
+pub const MAX_MEMO_LEN: usize = 256;
+
+/// Most keys an `IntentExtension::References` may carry
+pub const MAX_REFERENCES: usize = 4;
+
+/// Whether `memo` fits in `MAX_MEMO_LEN` bytes without control characters
+pub fn is_valid_memo(memo: &str) -> bool {
+    memo.len() <= MAX_MEMO_LEN && !memo.chars().any(char::is_control)
+}
+
+/// SPL Token denomination of an intent's `amount`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenTransfer {
+    pub mint: Pubkey,
+    /// Mint decimals the sender saw when signing, enforced by `transfer_checked`
+    pub decimals: u8,
+}
+
+/// How a token intent's `amount` relates to a Token-2022 transfer fee
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFeeMode {
+    /// The sender pays `amount`; the recipient receives `amount` minus the fee
+    Gross,
+    /// The recipient receives `amount`; the sender pays `amount` plus the fee
+    Net,
+}
+
+/// Transfer fee a sender accepts on a Token-2022 intent
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransferFeeTerms {
+    pub mode: TransferFeeMode,
+    /// Largest fee, in base units, the sender accepts
+    pub max_fee: u64,
+}
+
+/// Account that funds rent the settlement creates on the recipient's behalf
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RentPayer {
+    Relayer,
+    Sender,
+}
+
+/// Fee a sender pays the relayer that settles the intent
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelayerFee {
+    /// Lamports paid from the sender's vault to the relayer
+    pub amount: u64,
+    /// Only this relayer may settle the intent; `None` pays any submitter
+    pub relayer: Option<Pubkey>,
+}
+
+/// Further recipient of a split payment intent
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SplitLeg {
+    pub recipient: Pubkey,
+    /// Lamports paid to `recipient`
+    pub amount: u64,
+}
+
+/// Recurring payment terms of a mandate intent
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MandateTerms {
+    /// Length of each period in seconds
+    pub period: u64,
+    /// Number of periods the recipient can pull in
+    pub periods: u32,
+}
+
+/// Hash-time lock of an intent whose funds go into escrow at settlement
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HashLock {
+    /// SHA-256 of the secret the recipient reveals to claim
+    pub hash: [u8; 32],
+    /// Unix time until which the recipient can claim; afterwards the funds
+    /// can only be refunded to the sender
+    pub deadline: i64,
+}
+
+/// Optional terms carried by a v2 intent. Each kind may appear at most once.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum IntentExtension {
+    /// Durable nonce value the sender reserved offline. Settlement requires
+    /// `intent.nonce_account` to still hold this value, so the program must
+    /// perform the advance: a durable transaction built on the same nonce
+    /// has already moved it past the committed value.
+    DurableNonce([u8; 32]),
+    /// Unix time before which the intent cannot settle, for post-dated payments
+    ValidAfter(u64),
+    /// Slot before which the intent cannot settle
+    ValidAfterSlot(u64),
+    /// Last slot in which the intent can settle, tighter than `intent.expiry`
+    ExpirySlot(u64),
+    /// Settle `amount` base units of an SPL Token mint instead of lamports
+    Token(TokenTransfer),
+    /// Accept the mint's transfer fee on a token intent; requires `Token`
+    TransferFee(TransferFeeTerms),
+    /// Create the recipient's associated token account if it does not exist;
+    /// requires `Token`
+    CreateRecipientTokenAccount(RentPayer),
+    /// Also pay each leg, in order, after `intent.to` receives
+    /// `intent.amount`. Lamport intents only, with 1 to `MAX_SPLIT_LEGS` legs.
+    Split(Vec<SplitLeg>),
+    /// Pay the settling relayer, in lamports whatever the intent's
+    /// denomination
+    RelayerFee(RelayerFee),
+    /// Only this account may fund settlement as the relayer, so a merchant
+    /// sponsoring its customers' intents is not billed for anyone else's
+    Sponsor(Pubkey),
+    /// UTF-8 note for the recipient, such as an order id, logged at
+    /// settlement. 1 to `MAX_MEMO_LEN` bytes without control characters, so
+    /// it stays on its own log line.
+    Memo(String),
+    /// Solana Pay reference keys, passed read-only at settlement so the
+    /// transaction can be found by them. 1 to `MAX_REFERENCES` keys.
+    References(Vec<Pubkey>),
+    /// Pay the invoice with this id that `intent.to` created; the intent's
+    /// amount and mint must match it
+    Invoice(u64),
+    /// Turn the intent into a mandate the recipient pulls up to
+    /// `intent.amount` lamports from every period, instead of a one-off
+    /// payment. Cannot be combined with `Token`, `Split`, `Invoice`,
+    /// `RelayerFee` or a durable nonce account, which activation would not
+    /// advance.
+    Mandate(MandateTerms),
+    /// Lock the payment in an escrow PDA at settlement, released to
+    /// `intent.to` on revealing the hash preimage before the deadline and
+    /// refundable to the sender after it. Lamport intents only; cannot be
+    /// combined with `Split`, `Invoice` or `Mandate`.
+    HashLock(HashLock),
+}
+
+/// Version 2 payload
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SolanaIntentV2 {
+    pub intent: SolanaIntent,
+    pub extensions: Vec<IntentExtension>,
+}
+
+/// A decoded `intent_data` payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntentEnvelope {
+    V1(SolanaIntent),
+    V2(SolanaIntentV2),
+}
+
+impl IntentEnvelope {
+    /// Decode `version || payload`, rejecting unknown versions, or an
+    /// unprefixed legacy `SolanaIntent` as v1
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let envelope = match Self::unpack_versioned(data) {
+            Ok(envelope) => envelope,
+            Err(error) => SolanaIntent::try_from_slice(data)
+                .map(Self::V1)
+                .map_err(|_| error)?,
+        };
+
+        envelope.validate_extensions()?;
+        Ok(envelope)
+    }
+
+    fn unpack_versioned(data: &[u8]) -> Result<Self, TossError> {
+        let (&version, payload) = data.split_first().ok_or(TossError::MalformedIntent)?;
+
+        match version {
+            INTENT_V1 => SolanaIntent::try_from_slice(payload).map(Self::V1),
+            INTENT_V2 => SolanaIntentV2::try_from_slice(payload).map(Self::V2),
+            _ => return Err(TossError::UnsupportedIntentVersion),
+        }
+        .map_err(|_| TossError::MalformedIntent)
+    }
+
+    fn validate_extensions(&self) -> Result<(), TossError> {
+        let extensions = self.extensions();
+        for (i, extension) in extensions.iter().enumerate() {
+            if extensions[..i]
+                .iter()
+                .any(|other| mem::discriminant(other) == mem::discriminant(extension))
+            {
+                return Err(TossError::InvalidIntentExtension);
+            }
+            match extension {
+                IntentExtension::DurableNonce(_) if self.intent().nonce_account.is_none() => {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::ValidAfter(time) if *time > self.intent().expiry => {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::TransferFee(_)
+                | IntentExtension::CreateRecipientTokenAccount(_)
+                    if self.token().is_none() =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::Sponsor(sponsor)
+                    if self
+                        .relayer_fee()
+                        .and_then(|fee| fee.relayer)
+                        .is_some_and(|relayer| relayer != *sponsor) =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::Split(legs)
+                    if legs.is_empty()
+                        || legs.len() > MAX_SPLIT_LEGS
+                        || self.token().is_some()
+                        || self.total_amount().is_none() =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::Memo(memo) if memo.is_empty() || !is_valid_memo(memo) => {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::References(references)
+                    if references.is_empty() || references.len() > MAX_REFERENCES =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::Mandate(terms)
+                    if terms.period == 0
+                        || terms.periods == 0
+                        || self.intent().nonce_account.is_some()
+                        || self.intent().nonce_auth.is_some()
+                        || self.token().is_some()
+                        || self.invoice().is_some()
+                        || self.relayer_fee().is_some()
+                        || !self.split_legs().is_empty() =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                IntentExtension::HashLock(_)
+                    if self.token().is_some()
+                        || self.invoice().is_some()
+                        || self.mandate().is_some()
+                        || !self.split_legs().is_empty() =>
+                {
+                    return Err(TossError::InvalidIntentExtension);
+                }
+                _ => {}
+            }
+        }
+        if let (Some(valid_after_slot), Some(expiry_slot)) =
+            (self.valid_after_slot(), self.expiry_slot())
+        {
+            if valid_after_slot > expiry_slot {
+                return Err(TossError::InvalidIntentExtension);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the intent's time and slot bounds against `clock`
+    pub fn check_validity(&self, clock: &Clock) -> Result<(), TossError> {
+        let current_time = clock.unix_timestamp as u64;
+        if current_time > self.intent().expiry
+            || self.expiry_slot().is_some_and(|slot| clock.slot > slot)
+        {
+            return Err(TossError::IntentExpired);
+        }
+        if self.valid_after().is_some_and(|time| current_time < time)
+            || self
+                .valid_after_slot()
+                .is_some_and(|slot| clock.slot < slot)
+        {
+            return Err(TossError::IntentNotYetValid);
+        }
+        Ok(())
+    }
+
+    /// Encode as `version || payload`
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![self.version()];
+        match self {
+            Self::V1(intent) => data.extend(borsh::to_vec(intent).unwrap()),
+            Self::V2(intent) => data.extend(borsh::to_vec(intent).unwrap()),
+        }
+        data
+    }
+
+    pub fn version(&self) -> u8 {
+        match self {
+            Self::V1(_) => INTENT_V1,
+            Self::V2(_) => INTENT_V2,
+        }
+    }
+
+    /// Fields shared by every intent version
+    pub fn intent(&self) -> &SolanaIntent {
+        match self {
+            Self::V1(intent) => intent,
+            Self::V2(v2) => &v2.intent,
+        }
+    }
+
+    /// Optional terms; always empty for v1 intents
+    pub fn extensions(&self) -> &[IntentExtension] {
+        match self {
+            Self::V1(_) => &[],
+            Self::V2(v2) => &v2.extensions,
+        }
+    }
+
+    /// Durable nonce value committed by `IntentExtension::DurableNonce`
+    pub fn durable_nonce(&self) -> Option<&[u8; 32]> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::DurableNonce(nonce) => Some(nonce),
+                _ => None,
+            })
+    }
+
+    /// Token denomination set by `IntentExtension::Token`
+    pub fn token(&self) -> Option<&TokenTransfer> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Token(token) => Some(token),
+                _ => None,
+            })
+    }
+
+    /// Transfer fee terms set by `IntentExtension::TransferFee`
+    pub fn transfer_fee(&self) -> Option<&TransferFeeTerms> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::TransferFee(terms) => Some(terms),
+                _ => None,
+            })
+    }
+
+    /// Payer of the recipient token account set by
+    /// `IntentExtension::CreateRecipientTokenAccount`
+    pub fn recipient_token_account_payer(&self) -> Option<RentPayer> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::CreateRecipientTokenAccount(payer) => Some(*payer),
+                _ => None,
+            })
+    }
+
+    /// Further legs set by `IntentExtension::Split`; empty for single-recipient
+    /// intents
+    pub fn split_legs(&self) -> &[SplitLeg] {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Split(legs) => Some(&legs[..]),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// `intent.amount` plus every split leg, or `None` on overflow
+    pub fn total_amount(&self) -> Option<u64> {
+        self.split_legs()
+            .iter()
+            .try_fold(self.intent().amount, |total, leg| {
+                total.checked_add(leg.amount)
+            })
+    }
+
+    /// Lamports this intent pays `recipient` as `intent.to` and in split
+    /// legs, or `None` if it pays them nothing
+    pub fn amount_to(&self, recipient: &Pubkey) -> Option<u64> {
+        let intent = self.intent();
+        let legs = self
+            .split_legs()
+            .iter()
+            .map(|leg| (&leg.recipient, leg.amount));
+        std::iter::once((&intent.to, intent.amount))
+            .chain(legs)
+            .filter(|(to, _)| *to == recipient)
+            .map(|(_, amount)| amount)
+            .reduce(u64::saturating_add)
+    }
+
+    /// Relayer fee set by `IntentExtension::RelayerFee`
+    pub fn relayer_fee(&self) -> Option<&RelayerFee> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::RelayerFee(fee) => Some(fee),
+                _ => None,
+            })
+    }
+
+    /// Sponsor pinned by `IntentExtension::Sponsor`
+    pub fn sponsor(&self) -> Option<&Pubkey> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Sponsor(sponsor) => Some(sponsor),
+                _ => None,
+            })
+    }
+
+    /// Memo set by `IntentExtension::Memo`
+    pub fn memo(&self) -> Option<&str> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Memo(memo) => Some(memo.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Reference keys set by `IntentExtension::References`; empty without one
+    pub fn references(&self) -> &[Pubkey] {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::References(references) => Some(&references[..]),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// Invoice id set by `IntentExtension::Invoice`
+    pub fn invoice(&self) -> Option<u64> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Invoice(invoice_id) => Some(*invoice_id),
+                _ => None,
+            })
+    }
+
+    /// Recurring terms set by `IntentExtension::Mandate`
+    pub fn mandate(&self) -> Option<&MandateTerms> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::Mandate(terms) => Some(terms),
+                _ => None,
+            })
+    }
+
+    /// Hash lock set by `IntentExtension::HashLock`
+    pub fn hash_lock(&self) -> Option<&HashLock> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::HashLock(lock) => Some(lock),
+                _ => None,
+            })
+    }
+
+    /// Not-before time set by `IntentExtension::ValidAfter`
+    pub fn valid_after(&self) -> Option<u64> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::ValidAfter(time) => Some(*time),
+                _ => None,
+            })
+    }
+
+    /// Not-before slot set by `IntentExtension::ValidAfterSlot`
+    pub fn valid_after_slot(&self) -> Option<u64> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::ValidAfterSlot(slot) => Some(*slot),
+                _ => None,
+            })
+    }
+
+    /// Last settlement slot set by `IntentExtension::ExpirySlot`
+    pub fn expiry_slot(&self) -> Option<u64> {
+        self.extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                IntentExtension::ExpirySlot(slot) => Some(*slot),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::pubkey::Pubkey;
+
+    fn intent() -> SolanaIntent {
+        SolanaIntent {
+            from: Pubkey::new_unique(),
+            to: Pubkey::new_unique(),
+            amount: 1000000,
+            nonce: 1,
+            expiry: 9999999999,
+            nonce_account: None,
+            nonce_auth: None,
+        }
+    }
+
+    #[test]
+    fn test_v1_round_trip_keeps_original_layout() {
+        let intent = intent();
+        let packed = IntentEnvelope::V1(intent.clone()).pack();
+
+        assert_eq!(packed[0], INTENT_V1);
+        assert_eq!(&packed[1..], borsh::to_vec(&intent).unwrap().as_slice());
+        assert_eq!(
+            IntentEnvelope::unpack(&packed).unwrap(),
+            IntentEnvelope::V1(intent)
+        );
+    }
+
+    #[test]
+    fn test_unprefixed_legacy_layout_decodes_as_v1() {
+        let mut intent = intent();
+        assert_eq!(
+            IntentEnvelope::unpack(&borsh::to_vec(&intent).unwrap()).unwrap(),
+            IntentEnvelope::V1(intent.clone())
+        );
+
+        intent.nonce_account = Some(Pubkey::new_unique());
+        intent.nonce_auth = Some(Pubkey::new_unique());
+        assert_eq!(
+            IntentEnvelope::unpack(&borsh::to_vec(&intent).unwrap()).unwrap(),
+            IntentEnvelope::V1(intent)
+        );
+    }
+
+    #[test]
+    fn test_v2_round_trip() {
+        let mut intent = intent();
+        intent.nonce_account = Some(Pubkey::new_unique());
+        let envelope = IntentEnvelope::V2(SolanaIntentV2 {
+            intent,
+            extensions: vec![IntentExtension::DurableNonce([9; 32])],
+        });
+        let packed = envelope.pack();
+
+        assert_eq!(packed[0], INTENT_V2);
+        let unpacked = IntentEnvelope::unpack(&packed).unwrap();
+        assert_eq!(unpacked, envelope);
+        assert_eq!(unpacked.durable_nonce(), Some(&[9; 32]));
+    }
+
+    #[test]
+    fn test_invalid_extensions_are_rejected() {
+        let mut intent = intent();
+        let without_nonce_account = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent.clone(),
+            extensions: vec![IntentExtension::DurableNonce([9; 32])],
+        });
+        assert_eq!(
+            IntentEnvelope::unpack(&without_nonce_account.pack()),
+            Err(TossError::InvalidIntentExtension.into())
+        );
+
+        intent.nonce_account = Some(Pubkey::new_unique());
+        let duplicated = IntentEnvelope::V2(SolanaIntentV2 {
+            intent,
+            extensions: vec![
+                IntentExtension::DurableNonce([9; 32]),
+                IntentExtension::DurableNonce([8; 32]),
+            ],
+        });
+        assert_eq!(
+            IntentEnvelope::unpack(&duplicated.pack()),
+            Err(TossError::InvalidIntentExtension.into())
+        );
+    }
+
+    #[test]
+    fn test_inverted_validity_window_is_rejected() {
+        let after_expiry = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(),
+            extensions: vec![IntentExtension::ValidAfter(9999999999 + 1)],
+        });
+        assert_eq!(
+            IntentEnvelope::unpack(&after_expiry.pack()),
+            Err(TossError::InvalidIntentExtension.into())
+        );
+
+        let inverted_slots = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(),
+            extensions: vec![
+                IntentExtension::ExpirySlot(10),
+                IntentExtension::ValidAfterSlot(11),
+            ],
+        });
+        assert_eq!(
+            IntentEnvelope::unpack(&inverted_slots.pack()),
+            Err(TossError::InvalidIntentExtension.into())
+        );
+    }
+
+    #[test]
+    fn test_validity_window() {
+        let envelope = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(),
+            extensions: vec![
+                IntentExtension::ValidAfter(1000),
+                IntentExtension::ValidAfterSlot(100),
+                IntentExtension::ExpirySlot(200),
+            ],
+        });
+        let clock = |slot, unix_timestamp| Clock {
+            slot,
+            unix_timestamp,
+            ..Clock::default()
+        };
+
+        assert_eq!(envelope.check_validity(&clock(100, 1000)), Ok(()));
+        assert_eq!(envelope.check_validity(&clock(200, 5000)), Ok(()));
+        assert_eq!(
+            envelope.check_validity(&clock(150, 999)),
+            Err(TossError::IntentNotYetValid)
+        );
+        assert_eq!(
+            envelope.check_validity(&clock(99, 5000)),
+            Err(TossError::IntentNotYetValid)
+        );
+        assert_eq!(
+            envelope.check_validity(&clock(201, 5000)),
+            Err(TossError::IntentExpired)
+        );
+        assert_eq!(
+            envelope.check_validity(&clock(150, 9999999999 + 1)),
+            Err(TossError::IntentExpired)
+        );
+    }
+
+    #[test]
+    fn test_split_legs_are_bounded() {
+        let leg = |amount| SplitLeg {
+            recipient: Pubkey::new_unique(),
+            amount,
+        };
+        let split = |legs: Vec<SplitLeg>| {
+            IntentEnvelope::V2(SolanaIntentV2 {
+                intent: intent(),
+                extensions: vec![IntentExtension::Split(legs)],
+            })
+            .pack()
+        };
+
+        let envelope = IntentEnvelope::unpack(&split(vec![leg(5), leg(7)])).unwrap();
+        assert_eq!(envelope.split_legs().len(), 2);
+        assert_eq!(envelope.total_amount(), Some(1000000 + 12));
+
+        for legs in [
+            vec![],
+            (0..=MAX_SPLIT_LEGS).map(|_| leg(1)).collect(),
+            vec![leg(u64::MAX)],
+        ] {
+            assert_eq!(
+                IntentEnvelope::unpack(&split(legs)),
+                Err(TossError::InvalidIntentExtension.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_sponsor_must_agree_with_fee_relayer() {
+        let sponsor = Pubkey::new_unique();
+        let envelope = |relayer| {
+            IntentEnvelope::V2(SolanaIntentV2 {
+                intent: intent(),
+                extensions: vec![
+                    IntentExtension::RelayerFee(RelayerFee { amount: 1, relayer }),
+                    IntentExtension::Sponsor(sponsor),
+                ],
+            })
+            .pack()
+        };
+
+        assert!(IntentEnvelope::unpack(&envelope(None)).is_ok());
+        assert!(IntentEnvelope::unpack(&envelope(Some(sponsor))).is_ok());
+        assert_eq!(
+            IntentEnvelope::unpack(&envelope(Some(Pubkey::new_unique()))),
+            Err(TossError::InvalidIntentExtension.into())
+        );
+    }
+
+    #[test]
+    fn test_memo_and_references_are_bounded() {
+        let envelope = |extension| {
+            IntentEnvelope::V2(SolanaIntentV2 {
+                intent: intent(),
+                extensions: vec![extension],
+            })
+            .pack()
+        };
+
+        let memo =
+            IntentEnvelope::unpack(&envelope(IntentExtension::Memo("order-42".into()))).unwrap();
+        assert_eq!(memo.memo(), Some("order-42"));
+        let references = vec![Pubkey::new_unique(); MAX_REFERENCES];
+        let referenced =
+            IntentEnvelope::unpack(&envelope(IntentExtension::References(references.clone())))
+                .unwrap();
+        assert_eq!(referenced.references(), &references[..]);
+
+        for extension in [
+            IntentExtension::Memo(String::new()),
+            IntentExtension::Memo("x".repeat(MAX_MEMO_LEN + 1)),
+            IntentExtension::Memo("paid\nProgram log: forged".into()),
+            IntentExtension::References(vec![]),
+            IntentExtension::References(vec![Pubkey::new_unique(); MAX_REFERENCES + 1]),
+        ] {
+            assert_eq!(
+                IntentEnvelope::unpack(&envelope(extension)),
+                Err(TossError::InvalidIntentExtension.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_memo_must_be_utf8() {
+        let mut packed = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(),
+            extensions: vec![IntentExtension::Memo("ab".into())],
+        })
+        .pack();
+        let len = packed.len();
+        packed[len - 1] = 0xFF;
+
+        assert_eq!(
+            IntentEnvelope::unpack(&packed),
+            Err(TossError::MalformedIntent.into())
+        );
+    }
+
+    #[test]
+    fn test_hash_lock_excludes_split_and_mandate() {
+        let lock = IntentExtension::HashLock(HashLock {
+            hash: [7; 32],
+            deadline: 1000,
+        });
+        let envelope = |extensions| {
+            IntentEnvelope::V2(SolanaIntentV2 {
+                intent: intent(),
+                extensions,
+            })
+            .pack()
+        };
+
+        assert!(IntentEnvelope::unpack(&envelope(vec![lock.clone()])).is_ok());
+        for other in [
+            IntentExtension::Split(vec![SplitLeg {
+                recipient: Pubkey::new_unique(),
+                amount: 1,
+            }]),
+            IntentExtension::Mandate(MandateTerms {
+                period: 60,
+                periods: 1,
+            }),
+        ] {
+            assert_eq!(
+                IntentEnvelope::unpack(&envelope(vec![lock.clone(), other])),
+                Err(TossError::InvalidIntentExtension.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_version_is_rejected() {
+        let mut packed = IntentEnvelope::V1(intent()).pack();
+        packed[0] = 0xFF;
+
+        assert_eq!(
+            IntentEnvelope::unpack(&packed),
+            Err(TossError::UnsupportedIntentVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_trailing_bytes_are_rejected() {
+        let mut packed = IntentEnvelope::V1(intent()).pack();
+        packed.push(0);
+
+        assert_eq!(
+            IntentEnvelope::unpack(&packed),
+            Err(TossError::MalformedIntent.into())
+        );
+        assert_eq!(
+            IntentEnvelope::unpack(&[]),
+            Err(TossError::MalformedIntent.into())
+        );
+    }
+}
@@ -1,4 +1,4 @@
-/**
+/*!
  * TOSS Intent Processor Program
  *
  * GAP #5 FIX: Missing Solana Program for Intent Settlement
@@ -14,21 +14,57 @@
  * 5. Handle failures deterministically
  */
 
+pub mod durable_nonce;
+pub mod ed25519;
+pub mod error;
+pub mod intent;
+pub mod signing;
+pub mod state;
+pub mod token;
+
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     clock::Clock,
     entrypoint,
     entrypoint::ProgramResult,
+    hash::hash,
     msg,
-    program::invoke,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
     pubkey::Pubkey,
+    rent::Rent,
     system_instruction,
     system_program,
-    sysvar::Sysvar,
+    sysvar::{instructions as sysvar_instructions, Sysvar},
+};
+
+use crate::{
+    error::TossError,
+    intent::{IntentEnvelope, RentPayer},
+    signing::{signing_preimage, CLUSTER},
+    state::{
+        Escrow, FraudRecord, Invoice, InvoiceStatus, Mandate, NonceTracker, SettlementReceipt,
+        Vault,
+    },
+    token::{TokenAccounts, TokenSettlement},
 };
 
+/// How `ProcessIntentBatch` handles an intent that cannot settle
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Fail the whole batch
+    AllOrNothing,
+    /// Leave the intent unsettled and settle the rest
+    ///
+    /// Every failure found before any account changes for the intent is
+    /// skipped: a message that does not decode or is not signed by its
+    /// sender, missing or bad accounts, expiry, replays, missing funds and
+    /// the like. A failure while moving the intent's funds still fails the
+    /// batch, as a failed CPI aborts the whole transaction.
+    SkipFailed,
+}
+
 /// Instruction enum for TOSS Intent Processor
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum TossIntentInstruction {
@@ -36,13 +72,276 @@ pub enum TossIntentInstruction {
     ProcessIntent {
         /// Ed25519 signature of the intent (64 bytes)
         signature: [u8; 64],
-        /// Serialized intent payload
+        /// Versioned intent payload, see `intent::IntentEnvelope`
         intent_data: Vec<u8>,
     },
+    /// Set how many nonces below the highest settled one may still settle
+    ///
+    /// Accounts:
+    /// 0. Sender (signer, pays for the tracker if it does not exist yet)
+    /// 1. Sender nonce tracker PDA (writable)
+    /// 2. System program
+    ConfigureNonceWindow {
+        /// New window size, between 1 and `state::MAX_NONCE_WINDOW`
+        window_size: u16,
+    },
+    /// Move lamports into the owner's spending vault, creating it first
+    ///
+    /// Accounts:
+    /// 0. Owner (signer and writable unless a funder is given)
+    /// 1. Owner vault PDA (writable)
+    /// 2. System program
+    /// 3. Funder (optional; signer, writable), paying the vault's rent and
+    ///    the deposit instead of the owner, so a sponsor can open a vault for
+    ///    an owner holding no SOL
+    Deposit { amount: u64 },
+    /// Start a timelocked withdrawal from the owner's vault, replacing any
+    /// pending one
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    RequestWithdrawal {
+        /// Mint to withdraw, the system program for lamports
+        mint: Pubkey,
+        /// Amount to withdraw, zero to cancel the pending withdrawal
+        amount: u64,
+    },
+    /// Move the matured lamport withdrawal out of the owner's vault, capped
+    /// at what intents settled in the meantime left spendable
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    /// 2. Destination (writable)
+    Withdraw,
+    /// Move the matured token withdrawal out of a token account owned by the
+    /// owner's vault, capped at its balance
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    /// 2. Mint
+    /// 3. Vault token account (writable)
+    /// 4. Destination token account (writable)
+    /// 5. SPL Token or Token-2022 program
+    WithdrawTokens { decimals: u8 },
+    /// Close the owner's vault, returning all of its lamports; needs a
+    /// matured lamport withdrawal of any amount and, while a bond is posted,
+    /// a matured bond release
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    /// 2. Destination (writable)
+    CloseVault,
+    /// Change how long the owner's withdrawals wait
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    ConfigureWithdrawalDelay {
+        /// Delay in seconds, at most `state::MAX_WITHDRAWAL_DELAY`
+        delay: u64,
+    },
+    /// Add lamports to the security bond of the owner's existing vault
+    ///
+    /// Accounts:
+    /// 0. Owner (signer, writable)
+    /// 1. Owner vault PDA (writable)
+    /// 2. System program
+    PostBond { amount: u64 },
+    /// Prove that a sender signed two intents with the same nonce and slash
+    /// their bond to a recipient of the one that could not settle, up to
+    /// the lamports that intent would have paid them
+    ///
+    /// Each recipient of a split intent reports separately. Token intents
+    /// are not backed by the lamport bond and cannot be reported.
+    ///
+    /// The transaction must carry an Ed25519Program instruction for each
+    /// intent: the settled one two instructions before this one and the
+    /// conflicting one immediately before it.
+    ///
+    /// Accounts:
+    /// 0. Reporter (signer, writable, funds the fraud record)
+    /// 1. Sender vault PDA (writable)
+    /// 2. Settlement receipt PDA of the settled intent
+    /// 3. Victim, `intent.to` or a split leg recipient of the conflicting
+    ///    intent (writable)
+    /// 4. Fraud record PDA (writable, created by this instruction)
+    /// 5. Instructions sysvar
+    /// 6. System program
+    ReportDoubleSpend {
+        settled_signature: [u8; 64],
+        settled_intent_data: Vec<u8>,
+        conflicting_signature: [u8; 64],
+        conflicting_intent_data: Vec<u8>,
+    },
+    /// Settle several intents in one instruction
+    ///
+    /// The Ed25519Program instruction immediately before this one verifies
+    /// one signature per intent, in batch order, each by the intent's sender
+    /// over its signing preimage. The intents are read from those messages,
+    /// so they are not repeated in this instruction's data. Return data is
+    /// a Borsh `Vec<u64>` with one result per intent: `0` if it settled,
+    /// otherwise `u64::from(ProgramError)`, which is the `TossError` code for
+    /// program errors.
+    ///
+    /// Accounts:
+    /// 0. Relayer (signer, funds receipts and new nonce trackers)
+    /// 1. System program
+    /// 2. Instructions sysvar
+    ///
+    /// Then for each intent, in batch order: recipient, sender nonce tracker
+    /// PDA (writable), settlement receipt PDA (writable), sender vault PDA
+    /// (writable), followed by the token, durable nonce, split leg,
+    /// reference, invoice and escrow accounts `ProcessIntent` takes. The
+    /// sender account is not passed, so batched intents cannot have the
+    /// sender pay for the recipient's token account. An intent whose message
+    /// does not decode, is not signed by its sender or sets only one of
+    /// `nonce_account` and `nonce_auth` takes no accounts.
+    ProcessIntentBatch { mode: BatchMode },
+    /// Process an offline intent, carrying its whole signing preimage so the
+    /// preceding Ed25519Program instruction can reference the signature,
+    /// sender key and message here instead of repeating them; see
+    /// `compact_signature_offsets`
+    ///
+    /// Accounts: as `ProcessIntent`
+    ProcessIntentCompact {
+        /// Ed25519 signature of the preimage (64 bytes)
+        signature: [u8; 64],
+        /// `signing::signing_preimage` of the versioned intent payload
+        preimage: Vec<u8>,
+    },
+    /// Create an invoice for a payer's intent to settle
+    ///
+    /// Accounts:
+    /// 0. Payee (signer, writable, funds the invoice)
+    /// 1. Invoice PDA (writable)
+    /// 2. System program
+    CreateInvoice {
+        /// Payee-chosen id, seeding the invoice PDA
+        invoice_id: u64,
+        /// Amount due, in lamports or base units of `mint`
+        amount: u64,
+        /// Mint to be paid in, the system program for lamports
+        mint: Pubkey,
+        /// Unix time after which the invoice can no longer be paid
+        expiry: i64,
+        /// Note to the payer, at most `intent::MAX_MEMO_LEN` bytes
+        memo: String,
+    },
+    /// Cancel an open invoice so no intent can pay it
+    ///
+    /// Accounts:
+    /// 0. Payee (signer)
+    /// 1. Invoice PDA (writable)
+    CancelInvoice { invoice_id: u64 },
+    /// Close a paid, cancelled or expired invoice, returning its rent
+    ///
+    /// Accounts:
+    /// 0. Payee (signer)
+    /// 1. Invoice PDA (writable)
+    /// 2. Destination (writable)
+    CloseInvoice { invoice_id: u64 },
+    /// Start the recurring payment signed as a mandate intent
+    ///
+    /// The Ed25519Program instruction immediately before this one verifies
+    /// the sender's signature over the intent, as for `ProcessIntent`.
+    ///
+    /// The intent's nonce is consumed, as if it had settled.
+    ///
+    /// Accounts:
+    /// 0. Payee, the intent's recipient (signer, writable, funds the mandate
+    ///    and a new nonce tracker)
+    /// 1. Mandate PDA (writable, created by this instruction)
+    /// 2. Payer nonce tracker PDA (writable, created on first use)
+    /// 3. Instructions sysvar
+    /// 4. System program
+    ActivateMandate {
+        /// Ed25519 signature of the intent (64 bytes)
+        signature: [u8; 64],
+        /// Versioned intent payload carrying `IntentExtension::Mandate`
+        intent_data: Vec<u8>,
+    },
+    /// Pull lamports from the payer's vault against an active mandate
+    ///
+    /// Accounts:
+    /// 0. Payee (signer, writable)
+    /// 1. Mandate PDA (writable)
+    /// 2. Payer vault PDA (writable)
+    PullMandate {
+        /// Lamports to pull, within what is left of the current period
+        amount: u64,
+    },
+    /// Revoke a mandate, before or after activation
+    ///
+    /// Accounts:
+    /// 0. Payer (signer, writable, funds the mandate if it is not active yet)
+    /// 1. Mandate PDA (writable)
+    /// 2. System program
+    RevokeMandate {
+        /// `intent_hash` of the signed mandate intent
+        intent_hash: [u8; 32],
+    },
+    /// Release a hash-locked intent's escrow to its recipient by revealing
+    /// the preimage before the deadline; anyone can submit it
+    ///
+    /// Accounts:
+    /// 0. Escrow PDA (writable)
+    /// 1. Recipient (writable)
+    /// 2. Escrow rent payer (writable)
+    ClaimEscrow {
+        /// Secret whose SHA-256 is the intent's hash lock
+        preimage: Vec<u8>,
+    },
+    /// Return a hash-locked intent's escrow to its sender once the deadline
+    /// has passed; anyone can submit it
+    ///
+    /// Accounts:
+    /// 0. Escrow PDA (writable)
+    /// 1. Sender (writable)
+    /// 2. Escrow rent payer (writable)
+    RefundEscrow,
+    /// Start the timelock after which `CloseVault` can return the owner's
+    /// bond; victims can still report double spends until it passes
+    ///
+    /// Accounts:
+    /// 0. Owner (signer)
+    /// 1. Owner vault PDA (writable)
+    RequestBondRelease,
+}
+
+/// Offset of the signature in packed `ProcessIntentCompact` data, after the
+/// one-byte instruction tag
+const COMPACT_SIGNATURE_OFFSET: usize = 1;
+
+/// Offset of the preimage in packed `ProcessIntentCompact` data, after the
+/// signature and the preimage's four-byte length
+const COMPACT_PREIMAGE_OFFSET: usize = COMPACT_SIGNATURE_OFFSET + 64 + 4;
+
+/// Offsets for an Ed25519Program instruction verifying the
+/// `ProcessIntentCompact` instruction at `instruction_index`, whose
+/// preimage is `preimage_len` bytes long
+///
+/// The public key is read from `intent.from` inside the preimage.
+pub fn compact_signature_offsets(
+    instruction_index: u16,
+    preimage_len: usize,
+) -> ed25519::Ed25519SignatureOffsets {
+    ed25519::Ed25519SignatureOffsets {
+        signature_offset: COMPACT_SIGNATURE_OFFSET as u16,
+        signature_instruction_index: instruction_index,
+        public_key_offset: (COMPACT_PREIMAGE_OFFSET + ed25519::SENDER_OFFSET_IN_PREIMAGE) as u16,
+        public_key_instruction_index: instruction_index,
+        message_data_offset: COMPACT_PREIMAGE_OFFSET as u16,
+        message_data_size: preimage_len as u16,
+        message_instruction_index: instruction_index,
+    }
 }
 
 /// Data structure for a TOSS Intent (matches Typescript SolanaIntent)
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
 pub struct SolanaIntent {
     pub from: Pubkey,
     pub to: Pubkey,
@@ -53,6 +352,12 @@ pub struct SolanaIntent {
     pub nonce_auth: Option<Pubkey>,
 }
 
+/// Hash identifying a signed intent; seeds its `SettlementReceipt`
+pub fn intent_hash(intent_data: &[u8]) -> [u8; 32] {
+    hash(intent_data).to_bytes()
+}
+
+#[cfg(not(feature = "no-entrypoint"))]
 entrypoint!(process_instruction);
 
 /// Main entry point for TOSS Intent Processor
@@ -74,12 +379,85 @@ pub fn process_instruction(
         } => {
             process_intent(program_id, accounts, &signature, &intent_data)
         }
+        TossIntentInstruction::ConfigureNonceWindow { window_size } => {
+            process_configure_nonce_window(program_id, accounts, window_size)
+        }
+        TossIntentInstruction::Deposit { amount } => process_deposit(program_id, accounts, amount),
+        TossIntentInstruction::RequestWithdrawal { mint, amount } => {
+            process_request_withdrawal(program_id, accounts, mint, amount)
+        }
+        TossIntentInstruction::Withdraw => process_withdraw(program_id, accounts),
+        TossIntentInstruction::WithdrawTokens { decimals } => {
+            process_withdraw_tokens(program_id, accounts, decimals)
+        }
+        TossIntentInstruction::CloseVault => process_close_vault(program_id, accounts),
+        TossIntentInstruction::ConfigureWithdrawalDelay { delay } => {
+            process_configure_withdrawal_delay(program_id, accounts, delay)
+        }
+        TossIntentInstruction::PostBond { amount } => {
+            process_post_bond(program_id, accounts, amount)
+        }
+        TossIntentInstruction::ReportDoubleSpend {
+            settled_signature,
+            settled_intent_data,
+            conflicting_signature,
+            conflicting_intent_data,
+        } => process_report_double_spend(
+            program_id,
+            accounts,
+            (&settled_signature, &settled_intent_data),
+            (&conflicting_signature, &conflicting_intent_data),
+        ),
+        TossIntentInstruction::ProcessIntentBatch { mode } => {
+            process_intent_batch(program_id, accounts, mode)
+        }
+        TossIntentInstruction::ProcessIntentCompact {
+            signature,
+            preimage,
+        } => {
+            let intent_data =
+                signing::intent_data(program_id, CLUSTER, &preimage).ok_or_else(|| {
+                    msg!(" Preimage is not signed for this program");
+                    ProgramError::from(TossError::MessageMismatch)
+                })?;
+            process_intent(program_id, accounts, &signature, intent_data)
+        }
+        TossIntentInstruction::CreateInvoice {
+            invoice_id,
+            amount,
+            mint,
+            expiry,
+            memo,
+        } => process_create_invoice(program_id, accounts, invoice_id, amount, mint, expiry, memo),
+        TossIntentInstruction::CancelInvoice { invoice_id } => {
+            process_cancel_invoice(program_id, accounts, invoice_id)
+        }
+        TossIntentInstruction::CloseInvoice { invoice_id } => {
+            process_close_invoice(program_id, accounts, invoice_id)
+        }
+        TossIntentInstruction::ActivateMandate {
+            signature,
+            intent_data,
+        } => process_activate_mandate(program_id, accounts, &signature, &intent_data),
+        TossIntentInstruction::PullMandate { amount } => {
+            process_pull_mandate(program_id, accounts, amount)
+        }
+        TossIntentInstruction::RevokeMandate { intent_hash } => {
+            process_revoke_mandate(program_id, accounts, &intent_hash)
+        }
+        TossIntentInstruction::ClaimEscrow { preimage } => {
+            process_claim_escrow(program_id, accounts, &preimage)
+        }
+        TossIntentInstruction::RefundEscrow => process_refund_escrow(program_id, accounts),
+        TossIntentInstruction::RequestBondRelease => {
+            process_request_bond_release(program_id, accounts)
+        }
     }
 }
 
 /// Process a TOSS intent through the settlement phase
 fn process_intent(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     accounts: &[AccountInfo],
     signature: &[u8; 64],
     intent_data: &[u8],
@@ -87,147 +465,2028 @@ fn process_intent(
     let account_iter = &mut accounts.iter();
 
     // Required accounts:
-    // 0. Sender account (signer, funding account)
+    // 0. Sender account (need not sign; its vault is debited)
     // 1. Recipient account (receiving lamports)
     // 2. System program
-    // 3. (Optional) Nonce account (if using durable nonce)
-    // 4. (Optional) Nonce authority (if using durable nonce)
+    // 3. Instructions sysvar
+    // 4. Sender nonce tracker PDA (writable, created on first use)
+    // 5. Settlement receipt PDA (writable, created by this instruction)
+    // 6. Relayer (signer, funds the receipt, a new nonce tracker and a
+    //    relayer-paid recipient token account; the intent's sponsor if it
+    //    pins one)
+    // 7. Sender vault PDA (writable)
+    // Token intents only:
+    //    Mint, vault token account, recipient token account, token program,
+    //    the Associated Token Account program if the intent creates the
+    //    recipient token account, and the SPL Memo program if the recipient
+    //    requires incoming memos
+    // Durable nonce intents only:
+    //    Nonce account, nonce authority (signer) and, if the program
+    //    advances the nonce, the RecentBlockhashes sysvar
+    // Split intents only:
+    //    Each leg's recipient (writable), in leg order
+    // Intents with reference keys only:
+    //    Each reference key (read-only), in intent order
+    // Invoice intents only:
+    //    Invoice PDA (writable)
+    // Hash-locked intents only:
+    //    Escrow PDA (writable, created by this instruction)
 
     let sender = next_account_info(account_iter)?;
     let recipient = next_account_info(account_iter)?;
     let system_program = next_account_info(account_iter)?;
+    let instructions_sysvar = next_account_info(account_iter)?;
+    let nonce_tracker = next_account_info(account_iter)?;
+    let receipt = next_account_info(account_iter)?;
+    let relayer = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
 
     // Parse intent
-    let intent = SolanaIntent::try_from_slice(intent_data)
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    let envelope = IntentEnvelope::unpack(intent_data)?;
+    let intent = envelope.intent();
+    let nonce_advanced = durable_nonce_advanced(&envelope, instructions_sysvar)?;
+    let (token, durable_nonce) = next_intent_extras(account_iter, &envelope, nonce_advanced)?;
+    let split_recipients = next_split_recipients(account_iter, &envelope)?;
+    let references = next_references(account_iter, &envelope)?;
+    let invoice = envelope
+        .invoice()
+        .map(|_| next_account_info(account_iter))
+        .transpose()?;
+    let escrow = envelope
+        .hash_lock()
+        .map(|_| next_account_info(account_iter))
+        .transpose()?;
+    let intent_accounts = IntentAccounts {
+        sender: Some(sender),
+        recipient,
+        nonce_tracker,
+        receipt,
+        vault,
+        token,
+        durable_nonce,
+        split_recipients,
+        references,
+        invoice,
+        escrow,
+    };
 
-    msg!(" Intent parsed: {} -> {}", intent.from, intent.to);
+    msg!(
+        " Intent v{} parsed: {} -> {}",
+        envelope.version(),
+        intent.from,
+        intent.to
+    );
 
     // Step 1: Verify signature
-    // The signature should be over the intent_data
-    verify_intent_signature(&intent.from, intent_data, signature)?;
+    // The signature should be over the domain-separated preimage of intent_data
+    let preimage = signing_preimage(program_id, CLUSTER, intent_data);
+    verify_intent_signature(instructions_sysvar, 1, &intent.from, &preimage, signature)?;
     msg!(" Signature verified");
 
+    let settlement = Settlement {
+        program_id,
+        system_program,
+        relayer,
+        clock: Clock::get()?,
+    };
+    let mut debits = Vec::new();
+    let checked = check_intent(
+        &settlement,
+        &intent_accounts,
+        &envelope,
+        intent_data,
+        &debits,
+    )?;
+    execute_intent(
+        &settlement,
+        &intent_accounts,
+        &envelope,
+        checked,
+        &mut debits,
+    )?;
+    pay_vault_debits(&debits)?;
+
+    msg!(" Intent settlement complete");
+
+    Ok(())
+}
+
+/// Settle the intents signed into the preceding Ed25519Program instruction
+fn process_intent_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mode: BatchMode,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let relayer = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let instructions_sysvar = next_account_info(account_iter)?;
+
+    if !sysvar_instructions::check_id(instructions_sysvar.key) {
+        msg!(" Instructions sysvar account mismatch");
+        return Err(TossError::InvalidInstructionsSysvar.into());
+    }
+    let current_index =
+        sysvar_instructions::load_current_index_checked(instructions_sysvar)? as usize;
+    if current_index == 0 {
+        msg!(" No Ed25519 instruction precedes the batch");
+        return Err(TossError::MissingSignatureInstruction.into());
+    }
+    let ed25519_ix =
+        sysvar_instructions::load_instruction_at_checked(current_index - 1, instructions_sysvar)?;
+    let payloads = ed25519::parse_signatures(&ed25519_ix)?;
+    if payloads.is_empty() {
+        msg!(" Ed25519 instruction carries no intents");
+        return Err(TossError::MissingSignatureInstruction.into());
+    }
+
+    let settlement = Settlement {
+        program_id,
+        system_program,
+        relayer,
+        clock: Clock::get()?,
+    };
+    let mut results = Vec::with_capacity(payloads.len());
+    let mut debits = Vec::new();
+    for (index, payload) in payloads.iter().enumerate() {
+        match check_batch_intent(
+            &settlement,
+            account_iter,
+            instructions_sysvar,
+            index,
+            payload,
+            &debits,
+        ) {
+            Ok((envelope, intent_accounts, checked)) => {
+                // Funds may already have moved when this fails, and a failed
+                // CPI aborts the transaction anyway, so it is never skipped
+                execute_intent(
+                    &settlement,
+                    &intent_accounts,
+                    &envelope,
+                    checked,
+                    &mut debits,
+                )?;
+                results.push(0);
+            }
+            Err(e) if mode == BatchMode::SkipFailed => {
+                msg!(" Batch intent {} skipped: {}", index, e);
+                results.push(u64::from(e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    pay_vault_debits(&debits)?;
+
+    let settled = results.iter().filter(|result| **result == 0).count();
+    msg!(" Batch settled {} of {} intents", settled, results.len());
+    set_return_data(&borsh::to_vec(&results)?);
+    Ok(())
+}
+
+/// Decode one intent of a batch, read its accounts and check it
+///
+/// An intent whose signed message does not decode, is not signed by its
+/// sender or sets only half of its durable nonce pair takes no accounts, so
+/// the intents after it still find theirs.
+fn check_batch_intent<'a, 'info, I>(
+    settlement: &Settlement<'a, 'info>,
+    account_iter: &mut I,
+    instructions_sysvar: &AccountInfo,
+    index: usize,
+    payload: &ed25519::SignedPayload,
+    debits: &[VaultDebit<'a, 'info>],
+) -> Result<(IntentEnvelope, IntentAccounts<'a, 'info>, CheckedIntent), ProgramError>
+where
+    I: Iterator<Item = &'a AccountInfo<'info>>,
+{
+    // The runtime verified every signature; each message must be a signing
+    // preimage for this program, and its signer the sender
+    let intent_data = signing::intent_data(settlement.program_id, CLUSTER, payload.message)
+        .ok_or_else(|| {
+            msg!(" Intent {} is not signed for this program", index);
+            ProgramError::from(TossError::MessageMismatch)
+        })?;
+    let envelope = IntentEnvelope::unpack(intent_data)?;
+    let intent = envelope.intent();
+    if payload.public_key != intent.from.as_ref() {
+        msg!(" Intent {} is not signed by its sender", index);
+        return Err(TossError::SignerMismatch.into());
+    }
+    let nonce_advanced = durable_nonce_advanced(&envelope, instructions_sysvar)?;
+
+    let recipient = next_account_info(account_iter)?;
+    let nonce_tracker = next_account_info(account_iter)?;
+    let receipt = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let (token, durable_nonce) = next_intent_extras(account_iter, &envelope, nonce_advanced)?;
+    let split_recipients = next_split_recipients(account_iter, &envelope)?;
+    let references = next_references(account_iter, &envelope)?;
+    let invoice = envelope
+        .invoice()
+        .map(|_| next_account_info(account_iter))
+        .transpose()?;
+    let escrow = envelope
+        .hash_lock()
+        .map(|_| next_account_info(account_iter))
+        .transpose()?;
+    let intent_accounts = IntentAccounts {
+        sender: None,
+        recipient,
+        nonce_tracker,
+        receipt,
+        vault,
+        token,
+        durable_nonce,
+        split_recipients,
+        references,
+        invoice,
+        escrow,
+    };
+
+    msg!(" Batch intent {}: {} -> {}", index, intent.from, intent.to);
+    let checked = check_intent(settlement, &intent_accounts, &envelope, intent_data, debits)?;
+    Ok((envelope, intent_accounts, checked))
+}
+
+/// Accounts and cluster state shared by every intent an instruction settles
+struct Settlement<'a, 'info> {
+    program_id: &'a Pubkey,
+    system_program: &'a AccountInfo<'info>,
+    /// Funds receipts, new nonce trackers and relayer-paid token accounts
+    relayer: &'a AccountInfo<'info>,
+    clock: Clock,
+}
+
+/// Accounts a single intent settles against
+struct IntentAccounts<'a, 'info> {
+    /// Absent in batches, which take the sender from the signed preimage
+    sender: Option<&'a AccountInfo<'info>>,
+    recipient: &'a AccountInfo<'info>,
+    nonce_tracker: &'a AccountInfo<'info>,
+    receipt: &'a AccountInfo<'info>,
+    vault: &'a AccountInfo<'info>,
+    token: Option<TokenAccounts<'a, 'info>>,
+    durable_nonce: Option<DurableNonceAccounts<'a, 'info>>,
+    /// Recipient of each split leg, in leg order
+    split_recipients: Vec<&'a AccountInfo<'info>>,
+    /// One account per intent reference key, in order
+    references: Vec<&'a AccountInfo<'info>>,
+    /// Invoice PDA the intent pays, if it names one
+    invoice: Option<&'a AccountInfo<'info>>,
+    /// Escrow PDA a hash-locked intent pays into
+    escrow: Option<&'a AccountInfo<'info>>,
+}
+
+/// Accounts a durable nonce intent adds after its token accounts
+struct DurableNonceAccounts<'a, 'info> {
+    nonce_account: &'a AccountInfo<'info>,
+    nonce_authority: &'a AccountInfo<'info>,
+    /// Present only when the program has to advance the nonce itself
+    recent_blockhashes: Option<&'a AccountInfo<'info>>,
+}
+
+/// What `check_intent` established for `execute_intent`
+struct CheckedIntent {
+    intent_hash: [u8; 32],
+    receipt_bump: u8,
+    vault: Vault,
+    token_settlement: Option<TokenSettlement>,
+    invoice: Option<Invoice>,
+    /// Bump of the escrow PDA a hash-locked intent creates
+    escrow_bump: Option<u8>,
+}
+
+/// Lamports an instruction moves out of a vault once all of its CPIs have
+/// run
+///
+/// The runtime rejects a CPI that sees only one side of an earlier direct
+/// lamport move, such as a relayer credited with its fee before funding the
+/// next receipt, so direct vault debits wait until the end.
+struct VaultDebit<'a, 'info> {
+    vault: &'a AccountInfo<'info>,
+    vault_state: Vault,
+    destination: &'a AccountInfo<'info>,
+    amount: u64,
+}
+
+/// Whether the transaction's first instruction already advanced the durable
+/// nonce of `envelope`, or `None` if it has no nonce account
+///
+/// Runs before any of the intent's accounts are read, so a batch skipping
+/// the intent leaves the next intent's accounts in place.
+fn durable_nonce_advanced(
+    envelope: &IntentEnvelope,
+    instructions_sysvar: &AccountInfo,
+) -> Result<Option<bool>, ProgramError> {
+    let intent = envelope.intent();
+    match (intent.nonce_account, intent.nonce_auth) {
+        (Some(nonce_account), Some(nonce_auth)) => {
+            transaction_advanced_nonce(instructions_sysvar, &nonce_account, &nonce_auth).map(Some)
+        }
+        (None, None) => Ok(None),
+        _ => {
+            msg!(" Intent sets only one of nonce account and authority");
+            Err(TossError::IncompleteNonceConfig.into())
+        }
+    }
+}
+
+/// Read the token and durable nonce accounts `envelope` needs, given what
+/// `durable_nonce_advanced` returned for it
+fn next_intent_extras<'a, 'info, I>(
+    account_iter: &mut I,
+    envelope: &IntentEnvelope,
+    nonce_advanced: Option<bool>,
+) -> Result<
+    (
+        Option<TokenAccounts<'a, 'info>>,
+        Option<DurableNonceAccounts<'a, 'info>>,
+    ),
+    ProgramError,
+>
+where
+    I: Iterator<Item = &'a AccountInfo<'info>>,
+{
+    let token = match envelope.token() {
+        Some(_) => Some(TokenAccounts::next(
+            account_iter,
+            envelope.recipient_token_account_payer().is_some(),
+        )?),
+        None => None,
+    };
+
+    let durable_nonce = match nonce_advanced {
+        Some(advanced) => Some(DurableNonceAccounts {
+            nonce_account: next_account_info(account_iter)?,
+            nonce_authority: next_account_info(account_iter)?,
+            recent_blockhashes: if advanced {
+                None
+            } else {
+                Some(next_account_info(account_iter)?)
+            },
+        }),
+        None => None,
+    };
+    Ok((token, durable_nonce))
+}
+
+/// Read one recipient account per split leg of `envelope`
+fn next_split_recipients<'a, 'info, I>(
+    account_iter: &mut I,
+    envelope: &IntentEnvelope,
+) -> Result<Vec<&'a AccountInfo<'info>>, ProgramError>
+where
+    I: Iterator<Item = &'a AccountInfo<'info>>,
+{
+    envelope
+        .split_legs()
+        .iter()
+        .map(|_| next_account_info(account_iter))
+        .collect()
+}
+
+/// Read one account per reference key of `envelope`
+fn next_references<'a, 'info, I>(
+    account_iter: &mut I,
+    envelope: &IntentEnvelope,
+) -> Result<Vec<&'a AccountInfo<'info>>, ProgramError>
+where
+    I: Iterator<Item = &'a AccountInfo<'info>>,
+{
+    envelope
+        .references()
+        .iter()
+        .map(|_| next_account_info(account_iter))
+        .collect()
+}
+
+/// Check everything settling a signed intent depends on, without changing
+/// any account, so a batch can skip the intent if this fails
+fn check_intent<'a, 'info>(
+    settlement: &Settlement<'a, 'info>,
+    accounts: &IntentAccounts<'a, 'info>,
+    envelope: &IntentEnvelope,
+    intent_data: &[u8],
+    debits: &[VaultDebit<'a, 'info>],
+) -> Result<CheckedIntent, ProgramError> {
+    let program_id = settlement.program_id;
+    let intent = envelope.intent();
+
     // Step 2: Verify sender matches
-    if *sender.key != intent.from {
+    if accounts
+        .sender
+        .is_some_and(|sender| *sender.key != intent.from)
+    {
         msg!(" Sender mismatch");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(TossError::SenderMismatch.into());
     }
 
     // Step 3: Verify recipient matches
-    if *recipient.key != intent.to {
+    if *accounts.recipient.key != intent.to {
         msg!(" Recipient mismatch");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(TossError::RecipientMismatch.into());
+    }
+    for (i, (leg, recipient)) in envelope
+        .split_legs()
+        .iter()
+        .zip(&accounts.split_recipients)
+        .enumerate()
+    {
+        if *recipient.key != leg.recipient {
+            msg!(" Split leg {} recipient mismatch", i);
+            return Err(TossError::RecipientMismatch.into());
+        }
+    }
+    for (i, (reference, account)) in envelope
+        .references()
+        .iter()
+        .zip(&accounts.references)
+        .enumerate()
+    {
+        if account.key != reference {
+            msg!(" Reference {} mismatch", i);
+            return Err(TossError::ReferenceMismatch.into());
+        }
+        if account.is_writable || account.is_signer {
+            msg!(" Reference {} must be read-only and unsigned", i);
+            return Err(TossError::ReferenceMismatch.into());
+        }
     }
 
-    // Step 4: Check expiry
-    let clock = Clock::get()?;
-    let current_time = clock.unix_timestamp as u64;
-    if current_time > intent.expiry {
-        msg!(" Intent has expired");
-        return Err(ProgramError::InvalidInstructionData);
+    if envelope.mandate().is_some() {
+        msg!(" Mandate intents are pulled with PullMandate, not settled");
+        return Err(TossError::InvalidMandate.into());
     }
 
-    // Step 5: Handle nonce account if present
-    if let (Some(nonce_account_pubkey), Some(nonce_auth_pubkey)) =
-        (intent.nonce_account, intent.nonce_auth)
-    {
-        msg!(" Processing with durable nonce account");
+    // Step 4: Check expiry and not-before bounds
+    if let Err(e) = envelope.check_validity(&settlement.clock) {
+        msg!(" Intent outside its validity window: {}", e);
+        return Err(e.into());
+    }
 
-        // Get nonce account
-        let nonce_account = next_account_info(account_iter)?;
-        let nonce_authority = next_account_info(account_iter)?;
+    // Step 5: Reject intents that already have a receipt
+    let intent_hash = intent_hash(intent_data);
+    let (receipt_address, receipt_bump) = SettlementReceipt::find_address(&intent_hash, program_id);
+    if *accounts.receipt.key != receipt_address {
+        msg!(" Receipt address mismatch");
+        return Err(TossError::InvalidReceiptAccount.into());
+    }
+    if !accounts.receipt.data_is_empty() {
+        msg!(" Intent already settled");
+        return Err(TossError::IntentAlreadySettled.into());
+    }
+    if !settlement.relayer.is_signer {
+        msg!(" Relayer must be a signer");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+    if let Some(sponsor) = envelope.sponsor() {
+        if settlement.relayer.key != sponsor {
+            msg!(" Intent is sponsored by {}", sponsor);
+            return Err(TossError::SponsorMismatch.into());
+        }
+    }
+    if let Some(relayer) = envelope.relayer_fee().and_then(|fee| fee.relayer) {
+        if *settlement.relayer.key != relayer {
+            msg!(" Intent pays relayer {}", relayer);
+            return Err(TossError::RelayerMismatch.into());
+        }
+    }
+    let vault = load_vault(program_id, accounts.vault, &intent.from)?;
 
-        // Verify nonce account public key
-        if nonce_account.key != &nonce_account_pubkey {
-            msg!(" Nonce account mismatch");
-            return Err(ProgramError::InvalidAccountData);
+    // Step 6: Reject replayed nonces
+    if let Some(tracker) = load_nonce_tracker(program_id, accounts.nonce_tracker, &intent.from)? {
+        if let Err(e) = tracker.check(intent.nonce) {
+            msg!(" Nonce {} rejected: {}", intent.nonce, e);
+            return Err(e.into());
         }
+    }
+
+    // Step 7: Check the durable nonce account if present
+    match (
+        &accounts.durable_nonce,
+        intent.nonce_account,
+        intent.nonce_auth,
+    ) {
+        (Some(durable_nonce), Some(nonce_account_pubkey), Some(nonce_auth_pubkey)) => {
+            msg!(" Processing with durable nonce account");
+
+            // Verify nonce account public key
+            if durable_nonce.nonce_account.key != &nonce_account_pubkey {
+                msg!(" Nonce account mismatch");
+                return Err(TossError::NonceAccountMismatch.into());
+            }
 
-        // Verify nonce authority
-        if nonce_authority.key != &nonce_auth_pubkey {
-            msg!(" Nonce authority mismatch");
-            return Err(ProgramError::InvalidAccountData);
+            // Verify nonce authority
+            if durable_nonce.nonce_authority.key != &nonce_auth_pubkey {
+                msg!(" Nonce authority mismatch");
+                return Err(TossError::NonceAuthorityMismatch.into());
+            }
+
+            // Verify nonce authority is a signer
+            if !durable_nonce.nonce_authority.is_signer {
+                msg!(" Nonce authority must be a signer");
+                return Err(TossError::MissingRequiredSigner.into());
+            }
+
+            // Validate nonce account state
+            validate_nonce_account(
+                durable_nonce.nonce_account,
+                &nonce_auth_pubkey,
+                envelope.durable_nonce(),
+            )?;
+            msg!(" Nonce account validated");
         }
+        _ => msg!("️  No durable nonce account, relying on nonce tracker"),
+    }
 
-        // Verify nonce authority is a signer
-        if !nonce_authority.is_signer {
-            msg!(" Nonce authority must be a signer");
-            return Err(ProgramError::MissingRequiredSignature);
+    // Step 8: Check the sender can fund the transfer and relayer fee
+    let relayer_fee = envelope.relayer_fee().map_or(0, |fee| fee.amount);
+    let token_settlement = match (envelope.token(), &accounts.token) {
+        (Some(token), Some(token_accounts)) => {
+            if let Some(rent_payer) = envelope.recipient_token_account_payer() {
+                token_accounts.check_destination(
+                    recipient_token_account_payer(settlement, accounts, rent_payer)?,
+                    accounts.recipient,
+                )?;
+            }
+            let token_settlement = token_accounts.validate(
+                intent,
+                accounts.vault.key,
+                token,
+                envelope.transfer_fee(),
+                settlement.clock.epoch,
+            )?;
+            if token_settlement.source_balance < token_settlement.debit {
+                msg!(" Vault token balance too low");
+                return Err(TossError::InsufficientFunds.into());
+            }
+            Some(token_settlement)
         }
+        _ => None,
+    };
+    let lamports = match token_settlement {
+        Some(_) => relayer_fee,
+        None => envelope
+            .total_amount()
+            .and_then(|total| total.checked_add(relayer_fee))
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+    };
+    let queued = debits
+        .iter()
+        .filter(|debit| debit.vault.key == accounts.vault.key)
+        .fold(0u64, |total, debit| total.saturating_add(debit.amount));
+    let spendable = spendable_lamports(accounts.vault, &vault)?.saturating_sub(queued);
+    if spendable < lamports {
+        msg!(
+            " Vault holds {} spendable lamports, {} needed",
+            spendable,
+            lamports
+        );
+        return Err(TossError::InsufficientFunds.into());
+    }
 
-        // Validate nonce account structure
-        validate_nonce_account(nonce_account)?;
-        msg!(" Nonce account validated");
+    // Step 9: Check the intent pays its invoice in full, after any transfer
+    // fee withheld from the payee
+    let invoice = match (envelope.invoice(), accounts.invoice) {
+        (Some(invoice_id), Some(invoice_account)) => {
+            let invoice = load_invoice(program_id, invoice_account, &intent.to, invoice_id)?;
+            if let Err(e) = invoice.check_payable(settlement.clock.unix_timestamp) {
+                msg!(" Invoice {} cannot be paid: {}", invoice_id, e);
+                return Err(e.into());
+            }
+            let mint = envelope
+                .token()
+                .map_or(system_program::ID, |token| token.mint);
+            let received = token_settlement.map_or(intent.amount, |token_settlement| {
+                token_settlement.debit - token_settlement.fee
+            });
+            if invoice.amount != received || invoice.mint != mint {
+                msg!(" Invoice asks for {} of {}", invoice.amount, invoice.mint);
+                return Err(TossError::InvoiceMismatch.into());
+            }
+            Some(invoice)
+        }
+        _ => None,
+    };
 
-        // After transfer, advance the nonce
-        // This prevents replay attacks
-        let nonce_advance_ix = system_instruction::advance_nonce_account(
-            nonce_account.key,
-            nonce_authority.key,
-        );
-        invoke(&nonce_advance_ix, &[nonce_account.clone(), nonce_authority.clone(), system_program.clone()])?;
-        msg!(" Nonce advanced");
+    // Step 10: Check a hash-locked intent can still be locked
+    let escrow_bump = match (envelope.hash_lock(), accounts.escrow) {
+        (Some(lock), Some(escrow)) => {
+            if settlement.clock.unix_timestamp > lock.deadline {
+                msg!(" Hash lock deadline {} has passed", lock.deadline);
+                return Err(TossError::HashLockExpired.into());
+            }
+            let (escrow_address, escrow_bump) = Escrow::find_address(&intent_hash, program_id);
+            if *escrow.key != escrow_address || !escrow.data_is_empty() {
+                msg!(" Escrow address mismatch or already in use");
+                return Err(TossError::InvalidEscrow.into());
+            }
+            Some(escrow_bump)
+        }
+        _ => None,
+    };
+
+    Ok(CheckedIntent {
+        intent_hash,
+        receipt_bump,
+        vault,
+        token_settlement,
+        invoice,
+        escrow_bump,
+    })
+}
+
+/// Consume the nonce, move the funds and record the receipt of an intent
+/// that passed `check_intent`
+fn execute_intent<'a, 'info>(
+    settlement: &Settlement<'a, 'info>,
+    accounts: &IntentAccounts<'a, 'info>,
+    envelope: &IntentEnvelope,
+    checked: CheckedIntent,
+    debits: &mut Vec<VaultDebit<'a, 'info>>,
+) -> ProgramResult {
+    let intent = envelope.intent();
+    let relayer = settlement.relayer;
+    let system_program = settlement.system_program;
+    let CheckedIntent {
+        intent_hash,
+        receipt_bump,
+        vault: vault_state,
+        token_settlement,
+        invoice,
+        escrow_bump,
+    } = checked;
+
+    // Step 6: Consume the nonce
+    consume_intent_nonce(
+        settlement.program_id,
+        accounts.nonce_tracker,
+        relayer,
+        system_program,
+        intent,
+    )?;
+    msg!(" Nonce {} consumed", intent.nonce);
+
+    // Step 7: Advance the nonce so the intent cannot be replayed against it,
+    // unless the transaction already did as its durable-nonce instruction
+    if let Some(durable_nonce) = &accounts.durable_nonce {
+        match durable_nonce.recent_blockhashes {
+            None => msg!(" Nonce already advanced by the transaction"),
+            Some(recent_blockhashes) => {
+                let nonce_advance_ix = system_instruction::advance_nonce_account(
+                    durable_nonce.nonce_account.key,
+                    durable_nonce.nonce_authority.key,
+                );
+                invoke(
+                    &nonce_advance_ix,
+                    &[
+                        durable_nonce.nonce_account.clone(),
+                        recent_blockhashes.clone(),
+                        durable_nonce.nonce_authority.clone(),
+                    ],
+                )?;
+                msg!(" Nonce advanced");
+            }
+        }
+    }
+
+    // Step 8: Execute transfer
+    let vault = accounts.vault;
+    let mint = match (envelope.token(), &accounts.token, token_settlement) {
+        (Some(token), Some(token_accounts), Some(token_settlement)) => {
+            msg!(
+                " Executing transfer of {} base units of {}",
+                intent.amount,
+                token.mint
+            );
+            if let Some(rent_payer) = envelope.recipient_token_account_payer() {
+                let payer = recipient_token_account_payer(settlement, accounts, rent_payer)?;
+                token_accounts.create_destination(payer, accounts.recipient, system_program)?;
+            }
+            let memo = envelope.memo().map_or_else(
+                || format!("TOSS intent {}", Pubkey::new_from_array(intent_hash)),
+                str::to_owned,
+            );
+            let vault_seeds: &[&[u8]] = &[
+                Vault::SEED_PREFIX,
+                intent.from.as_ref(),
+                &[vault_state.bump],
+            ];
+            token_accounts.transfer(
+                vault,
+                vault_seeds,
+                &token_settlement,
+                token.decimals,
+                memo.as_bytes(),
+            )?;
+            token.mint
+        }
+        _ => {
+            let destination = match (envelope.hash_lock(), accounts.escrow, escrow_bump) {
+                (Some(lock), Some(escrow), Some(escrow_bump)) => {
+                    create_pda_account(
+                        relayer,
+                        escrow,
+                        system_program,
+                        settlement.program_id,
+                        Escrow::LEN,
+                        &[Escrow::SEED_PREFIX, &intent_hash, &[escrow_bump]],
+                    )?;
+                    Escrow {
+                        is_initialized: true,
+                        intent_hash,
+                        sender: intent.from,
+                        recipient: intent.to,
+                        amount: intent.amount,
+                        hash: lock.hash,
+                        deadline: lock.deadline,
+                        rent_payer: *relayer.key,
+                        bump: escrow_bump,
+                    }
+                    .serialize(&mut &mut escrow.data.borrow_mut()[..])?;
+                    msg!(
+                        " Locking {} lamports in escrow {}",
+                        intent.amount,
+                        escrow.key
+                    );
+                    escrow
+                }
+                _ => {
+                    msg!(" Executing transfer of {} lamports", intent.amount);
+                    accounts.recipient
+                }
+            };
+            debits.push(VaultDebit {
+                vault,
+                vault_state: vault_state.clone(),
+                destination,
+                amount: intent.amount,
+            });
+            for (leg, recipient) in envelope.split_legs().iter().zip(&accounts.split_recipients) {
+                msg!(
+                    " Executing split transfer of {} lamports to {}",
+                    leg.amount,
+                    leg.recipient
+                );
+                debits.push(VaultDebit {
+                    vault,
+                    vault_state: vault_state.clone(),
+                    destination: recipient,
+                    amount: leg.amount,
+                });
+            }
+            system_program::ID
+        }
+    };
+
+    msg!(" Transfer completed successfully");
+    if let Some(memo) = envelope.memo() {
+        msg!(" Memo: {}", memo);
+    }
+
+    // Step 9: Record the settlement
+    let receipt = accounts.receipt;
+    create_pda_account(
+        relayer,
+        receipt,
+        system_program,
+        settlement.program_id,
+        SettlementReceipt::LEN,
+        &[
+            SettlementReceipt::SEED_PREFIX,
+            &intent_hash,
+            &[receipt_bump],
+        ],
+    )?;
+    SettlementReceipt {
+        is_initialized: true,
+        intent_hash,
+        sender: intent.from,
+        recipient: intent.to,
+        amount: envelope
+            .total_amount()
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+        mint,
+        nonce: intent.nonce,
+        slot: settlement.clock.slot,
+        settled_at: settlement.clock.unix_timestamp,
+        relayer: *relayer.key,
+        bump: receipt_bump,
+    }
+    .serialize(&mut &mut receipt.data.borrow_mut()[..])?;
+    msg!(" Receipt recorded at {}", receipt.key);
+
+    if let (Some(mut invoice), Some(invoice_account)) = (invoice, accounts.invoice) {
+        invoice.status = InvoiceStatus::Paid;
+        invoice.paid_by = intent.from;
+        invoice.paid_intent_hash = intent_hash;
+        invoice.serialize(&mut &mut invoice_account.data.borrow_mut()[..])?;
+        msg!(" Invoice {} paid", invoice.invoice_id);
+    }
+
+    // Step 10: Pay the relayer
+    if let Some(fee) = envelope.relayer_fee() {
+        msg!(" Paying relayer fee of {} lamports", fee.amount);
+        debits.push(VaultDebit {
+            vault,
+            vault_state,
+            destination: relayer,
+            amount: fee.amount,
+        });
+    }
+
+    Ok(())
+}
+
+/// Account paying for the recipient's new token account
+fn recipient_token_account_payer<'a, 'info>(
+    settlement: &Settlement<'a, 'info>,
+    accounts: &IntentAccounts<'a, 'info>,
+    rent_payer: RentPayer,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    match rent_payer {
+        RentPayer::Relayer => Ok(settlement.relayer),
+        RentPayer::Sender => match accounts.sender {
+            Some(sender) if sender.is_signer => Ok(sender),
+            _ => {
+                msg!(" Sender must sign to pay recipient token account rent");
+                Err(TossError::MissingRequiredSigner.into())
+            }
+        },
+    }
+}
+
+/// Fund the owner's vault, creating it on first deposit
+fn process_deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let funder = next_account_info(account_iter).unwrap_or(owner);
+
+    if !funder.is_signer {
+        msg!(" Funder must sign deposits");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+
+    if vault.data_is_empty() {
+        let (expected_address, bump) = Vault::find_address(owner.key, program_id);
+        if *vault.key != expected_address {
+            msg!(" Vault address mismatch");
+            return Err(TossError::InvalidVault.into());
+        }
+        create_pda_account(
+            funder,
+            vault,
+            system_program,
+            program_id,
+            Vault::LEN,
+            &[Vault::SEED_PREFIX, owner.key.as_ref(), &[bump]],
+        )?;
+        Vault::new(*owner.key, bump).serialize(&mut &mut vault.data.borrow_mut()[..])?;
+        msg!(" Vault created at {}", vault.key);
     } else {
-        msg!("️  No durable nonce account, using standard nonce");
+        load_vault(program_id, vault, owner.key)?;
     }
 
-    // Step 6: Execute transfer
-    msg!(" Executing transfer of {} lamports", intent.amount);
+    invoke(
+        &system_instruction::transfer(funder.key, vault.key, amount),
+        &[funder.clone(), vault.clone(), system_program.clone()],
+    )?;
+    msg!(" Deposited {} lamports", amount);
+    Ok(())
+}
 
-    let transfer_instruction = system_instruction::transfer(sender.key, recipient.key, intent.amount);
-    invoke(&transfer_instruction, &[sender.clone(), recipient.clone(), system_program.clone()])?;
+/// Start the owner's withdrawal clock
+fn process_request_withdrawal(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
 
-    msg!(" Transfer completed successfully");
-    msg!(" Intent settlement complete");
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    vault_state.request_withdrawal(mint, amount, Clock::get()?.unix_timestamp);
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+    msg!(
+        " Withdrawal of {} from {} unlocks at {}",
+        amount,
+        mint,
+        vault_state.withdrawal_unlocks_at
+    );
+    Ok(())
+}
+
+/// Move the owner's matured lamport withdrawal to `destination`
+fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let destination = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    let requested = take_matured_withdrawal(&mut vault_state, &system_program::ID)?;
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+
+    // Intents settled while the withdrawal was pending take precedence
+    let amount = requested.min(spendable_lamports(vault, &vault_state)?);
+    debit_vault(vault, &vault_state, destination, amount)?;
+    msg!(" Withdrew {} lamports", amount);
+    Ok(())
+}
+
+/// Move the owner's matured token withdrawal from a vault-owned token
+/// account to `destination`
+fn process_withdraw_tokens(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    decimals: u8,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let mint = next_account_info(account_iter)?;
+    let source = next_account_info(account_iter)?;
+    let destination = next_account_info(account_iter)?;
+    let token_program = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    if *token_program.key != spl_token::ID && *token_program.key != spl_token_2022::ID {
+        msg!(" Token program mismatch");
+        return Err(TossError::InvalidTokenProgram.into());
+    }
+    let requested = take_matured_withdrawal(&mut vault_state, mint.key)?;
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+
+    // Intents settled while the withdrawal was pending take precedence
+    let balance = token::check_token_account(source, token_program.key, mint.key, vault.key)?;
+    let amount = requested.min(balance);
+    let transfer_instruction = spl_token_2022::instruction::transfer_checked(
+        token_program.key,
+        source.key,
+        mint.key,
+        destination.key,
+        vault.key,
+        &[],
+        amount,
+        decimals,
+    )?;
+    invoke_signed(
+        &transfer_instruction,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            vault.clone(),
+            token_program.clone(),
+        ],
+        &[&[Vault::SEED_PREFIX, owner.key.as_ref(), &[vault_state.bump]]],
+    )?;
+    msg!(" Withdrew {} base units of {}", amount, mint.key);
+    Ok(())
+}
+
+/// Close the owner's vault, sending all of its lamports to `destination`
+fn process_close_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let destination = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    vault_state
+        .check_bond_released(Clock::get()?.unix_timestamp)
+        .map_err(|e| {
+            msg!(" Bond of {} lamports not released: {}", vault_state.bond, e);
+            ProgramError::from(e)
+        })?;
+    take_matured_withdrawal(&mut vault_state, &system_program::ID)?;
+
+    let lamports = vault.lamports();
+    **vault.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    vault.realloc(0, false)?;
+    vault.assign(&system_program::ID);
+
+    msg!(" Vault closed, {} lamports returned", lamports);
+    Ok(())
+}
+
+/// Change how long the owner's withdrawals wait
+fn process_configure_withdrawal_delay(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delay: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    vault_state.set_withdrawal_delay(delay, Clock::get()?.unix_timestamp)?;
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+    msg!(" Withdrawal delay set to {} seconds", delay);
+    Ok(())
+}
+
+/// Grow the owner's security bond
+fn process_post_bond(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    invoke(
+        &system_instruction::transfer(owner.key, vault.key, amount),
+        &[owner.clone(), vault.clone(), system_program.clone()],
+    )?;
+    vault_state.bond = vault_state
+        .bond
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+    msg!(" Bond raised to {} lamports", vault_state.bond);
+    Ok(())
+}
+
+/// Start the owner's bond release clock
+fn process_request_bond_release(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let owner = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+
+    let mut vault_state = load_owned_vault(program_id, vault, owner)?;
+    vault_state.request_bond_release(Clock::get()?.unix_timestamp);
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
+    msg!(" Bond releases at {}", vault_state.bond_unlocks_at);
+    Ok(())
+}
+
+/// Slash a sender's bond for signing two intents with one nonce
+fn process_report_double_spend(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    settled: (&[u8; 64], &[u8]),
+    conflicting: (&[u8; 64], &[u8]),
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let reporter = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+    let receipt = next_account_info(account_iter)?;
+    let victim = next_account_info(account_iter)?;
+    let fraud_record = next_account_info(account_iter)?;
+    let instructions_sysvar = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    if !reporter.is_signer {
+        msg!(" Reporter must be a signer");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+
+    // Step 1: Both intents must be validly signed by the sender
+    let (settled_signature, settled_data) = settled;
+    let (conflicting_signature, conflicting_data) = conflicting;
+    let settled_envelope = IntentEnvelope::unpack(settled_data)?;
+    let conflicting_envelope = IntentEnvelope::unpack(conflicting_data)?;
+    let settled_intent = settled_envelope.intent();
+    let conflicting_intent = conflicting_envelope.intent();
+    let settled_preimage = signing_preimage(program_id, CLUSTER, settled_data);
+    verify_intent_signature(
+        instructions_sysvar,
+        2,
+        &settled_intent.from,
+        &settled_preimage,
+        settled_signature,
+    )?;
+    let conflicting_preimage = signing_preimage(program_id, CLUSTER, conflicting_data);
+    verify_intent_signature(
+        instructions_sysvar,
+        1,
+        &conflicting_intent.from,
+        &conflicting_preimage,
+        conflicting_signature,
+    )?;
+
+    // Step 2: They must be different intents for the same sender and nonce
+    if settled_intent.from != conflicting_intent.from
+        || settled_intent.nonce != conflicting_intent.nonce
+        || settled_data == conflicting_data
+    {
+        msg!(" Intents do not share a sender and nonce");
+        return Err(TossError::IntentsDoNotConflict.into());
+    }
+    let sender = settled_intent.from;
+    let nonce = settled_intent.nonce;
+    if conflicting_envelope.token().is_some() {
+        msg!(" Token intents are not backed by the lamport bond");
+        return Err(TossError::TokenIntentNotSlashable.into());
+    }
+
+    // Step 3: One of them settled, so the other never can
+    let settled_hash = intent_hash(settled_data);
+    if *receipt.key != SettlementReceipt::find_address(&settled_hash, program_id).0
+        || receipt.owner != program_id
+        || receipt.data_is_empty()
+    {
+        msg!(" No settlement receipt for the settled intent");
+        return Err(TossError::IntentNotSettled.into());
+    }
+    let Some(owed) = conflicting_envelope.amount_to(victim.key) else {
+        msg!(" Victim is not paid by the conflicting intent");
+        return Err(TossError::VictimMismatch.into());
+    };
+
+    // Step 4: Compensate each recipient of a conflicting intent once
+    let conflicting_hash = intent_hash(conflicting_data);
+    let (record_address, record_bump) =
+        FraudRecord::find_address(&conflicting_hash, victim.key, program_id);
+    if *fraud_record.key != record_address {
+        msg!(" Fraud record address mismatch");
+        return Err(TossError::InvalidFraudRecord.into());
+    }
+    if !fraud_record.data_is_empty() {
+        msg!(" Double spend already reported");
+        return Err(TossError::DoubleSpendAlreadyReported.into());
+    }
+
+    // Step 5: Slash the bond, up to the lamports the victim was promised
+    let mut vault_state = load_vault(program_id, vault, &sender)?;
+    let slashed = vault_state.bond.min(owed);
+    vault_state.bond -= slashed;
+    vault_state.serialize(&mut &mut vault.data.borrow_mut()[..])?;
 
+    create_pda_account(
+        reporter,
+        fraud_record,
+        system_program,
+        program_id,
+        FraudRecord::LEN,
+        &[
+            FraudRecord::SEED_PREFIX,
+            &conflicting_hash,
+            victim.key.as_ref(),
+            &[record_bump],
+        ],
+    )?;
+    let record = FraudRecord {
+        is_initialized: true,
+        sender,
+        nonce,
+        settled_intent_hash: settled_hash,
+        conflicting_intent_hash: conflicting_hash,
+        victim: *victim.key,
+        slashed,
+        reporter: *reporter.key,
+        reported_at: Clock::get()?.unix_timestamp,
+        bump: record_bump,
+    };
+    record.serialize(&mut &mut fraud_record.data.borrow_mut()[..])?;
+
+    // Move lamports only after the CPI above, which may debit the victim as
+    // the reporter
+    **vault.try_borrow_mut_lamports()? = vault
+        .lamports()
+        .checked_sub(slashed)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **victim.try_borrow_mut_lamports()? = victim
+        .lamports()
+        .checked_add(slashed)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!(
+        " Slashed {} lamports from {} to {}",
+        slashed,
+        sender,
+        victim.key
+    );
+    Ok(())
+}
+
+/// Open an invoice for the payee
+fn process_create_invoice(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    invoice_id: u64,
+    amount: u64,
+    mint: Pubkey,
+    expiry: i64,
+    memo: String,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payee = next_account_info(account_iter)?;
+    let invoice = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    if !payee.is_signer {
+        msg!(" Payee must sign invoices");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+    if amount == 0 || expiry <= Clock::get()?.unix_timestamp || !intent::is_valid_memo(&memo) {
+        msg!(" Invoice needs an amount, a future expiry and a valid memo");
+        return Err(TossError::InvalidInvoiceTerms.into());
+    }
+
+    let (expected_address, bump) = Invoice::find_address(payee.key, invoice_id, program_id);
+    if *invoice.key != expected_address || !invoice.data_is_empty() {
+        msg!(" Invoice address mismatch or already in use");
+        return Err(TossError::InvalidInvoice.into());
+    }
+    create_pda_account(
+        payee,
+        invoice,
+        system_program,
+        program_id,
+        Invoice::space(&memo),
+        &[
+            Invoice::SEED_PREFIX,
+            payee.key.as_ref(),
+            &invoice_id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    Invoice {
+        is_initialized: true,
+        payee: *payee.key,
+        invoice_id,
+        amount,
+        mint,
+        expiry,
+        status: InvoiceStatus::Open,
+        paid_by: Pubkey::default(),
+        paid_intent_hash: [0; 32],
+        bump,
+        memo,
+    }
+    .serialize(&mut &mut invoice.data.borrow_mut()[..])?;
+    msg!(" Invoice {} created at {}", invoice_id, invoice.key);
+    Ok(())
+}
+
+/// Withdraw the payee's open invoice
+fn process_cancel_invoice(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    invoice_id: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payee = next_account_info(account_iter)?;
+    let invoice = next_account_info(account_iter)?;
+
+    let mut invoice_state = load_owned_invoice(program_id, invoice, payee, invoice_id)?;
+    if let Err(e) = invoice_state.cancel() {
+        msg!(" Invoice {} cannot be cancelled: {}", invoice_id, e);
+        return Err(e.into());
+    }
+    invoice_state.serialize(&mut &mut invoice.data.borrow_mut()[..])?;
+    msg!(" Invoice {} cancelled", invoice_id);
+    Ok(())
+}
+
+/// Close the payee's settled invoice, sending its rent to `destination`
+fn process_close_invoice(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    invoice_id: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payee = next_account_info(account_iter)?;
+    let invoice = next_account_info(account_iter)?;
+    let destination = next_account_info(account_iter)?;
+
+    let invoice_state = load_owned_invoice(program_id, invoice, payee, invoice_id)?;
+    if let Err(e) = invoice_state.check_closable(Clock::get()?.unix_timestamp) {
+        msg!(" Invoice {} cannot be closed: {}", invoice_id, e);
+        return Err(e.into());
+    }
+
+    let lamports = invoice.lamports();
+    **invoice.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    invoice.realloc(0, false)?;
+    invoice.assign(&system_program::ID);
+
+    msg!(
+        " Invoice {} closed, {} lamports returned",
+        invoice_id,
+        lamports
+    );
+    Ok(())
+}
+
+/// Create the mandate PDA for a signed mandate intent
+fn process_activate_mandate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    signature: &[u8; 64],
+    intent_data: &[u8],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payee = next_account_info(account_iter)?;
+    let mandate = next_account_info(account_iter)?;
+    let nonce_tracker = next_account_info(account_iter)?;
+    let instructions_sysvar = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    if !payee.is_signer {
+        msg!(" Payee must sign mandate activation");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+
+    // Step 1: The sender signed this mandate for this payee
+    let envelope = IntentEnvelope::unpack(intent_data)?;
+    let intent = envelope.intent();
+    let terms = envelope.mandate().ok_or_else(|| {
+        msg!(" Intent is not a mandate");
+        ProgramError::from(TossError::InvalidMandate)
+    })?;
+    let preimage = signing_preimage(program_id, CLUSTER, intent_data);
+    verify_intent_signature(instructions_sysvar, 1, &intent.from, &preimage, signature)?;
+    if *payee.key != intent.to {
+        msg!(" Payee is not the mandate's recipient");
+        return Err(TossError::RecipientMismatch.into());
+    }
+    let clock = Clock::get()?;
+    if let Err(e) = envelope.check_validity(&clock) {
+        msg!(" Mandate outside its validity window: {}", e);
+        return Err(e.into());
+    }
+
+    // Step 2: Activate it once, unless the payer revoked it first
+    let intent_hash = intent_hash(intent_data);
+    let (expected_address, bump) = Mandate::find_address(&intent.from, &intent_hash, program_id);
+    if *mandate.key != expected_address {
+        msg!(" Mandate address mismatch");
+        return Err(TossError::InvalidMandate.into());
+    }
+    if !mandate.data_is_empty() {
+        let state = load_mandate(program_id, mandate, &intent.from, &intent_hash)?;
+        msg!(" Mandate already exists");
+        return Err(if state.revoked {
+            TossError::MandateRevoked
+        } else {
+            TossError::MandateAlreadyActive
+        }
+        .into());
+    }
+
+    // Step 3: Use up the nonce, so no payment or other mandate can reuse it
+    consume_intent_nonce(program_id, nonce_tracker, payee, system_program, intent)?;
+
+    create_pda_account(
+        payee,
+        mandate,
+        system_program,
+        program_id,
+        Mandate::LEN,
+        &[
+            Mandate::SEED_PREFIX,
+            intent.from.as_ref(),
+            &intent_hash,
+            &[bump],
+        ],
+    )?;
+    Mandate {
+        is_initialized: true,
+        payer: intent.from,
+        payee: intent.to,
+        intent_hash,
+        amount_per_period: intent.amount,
+        period: terms.period,
+        periods: terms.periods,
+        started_at: clock.unix_timestamp,
+        current_period: 0,
+        pulled_in_period: 0,
+        total_pulled: 0,
+        revoked: false,
+        bump,
+    }
+    .serialize(&mut &mut mandate.data.borrow_mut()[..])?;
+    msg!(" Mandate activated at {}", mandate.key);
+    Ok(())
+}
+
+/// Pay the payee from the payer's vault under an active mandate
+fn process_pull_mandate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payee = next_account_info(account_iter)?;
+    let mandate = next_account_info(account_iter)?;
+    let vault = next_account_info(account_iter)?;
+
+    if !payee.is_signer {
+        msg!(" Payee must sign pulls");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+    // The mandate's address commits to the payer and intent it records
+    let recorded =
+        Mandate::try_from_slice(&mandate.data.borrow()).map_err(|_| TossError::InvalidMandate)?;
+    let mut state = load_mandate(program_id, mandate, &recorded.payer, &recorded.intent_hash)?;
+    if state.payee != *payee.key {
+        msg!(" Only the mandate's payee can pull");
+        return Err(TossError::RecipientMismatch.into());
+    }
+    if let Err(e) = state.pull(amount, Clock::get()?.unix_timestamp) {
+        msg!(" Pull of {} rejected: {}", amount, e);
+        return Err(e.into());
+    }
+    state.serialize(&mut &mut mandate.data.borrow_mut()[..])?;
+
+    let vault_state = load_vault(program_id, vault, &state.payer)?;
+    debit_vault(vault, &vault_state, payee, amount)?;
+    msg!(
+        " Pulled {} lamports in period {}",
+        amount,
+        state.current_period
+    );
+    Ok(())
+}
+
+/// Revoke the payer's mandate, recording the revocation if it was never
+/// activated
+fn process_revoke_mandate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    intent_hash: &[u8; 32],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let payer = next_account_info(account_iter)?;
+    let mandate = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    if !payer.is_signer {
+        msg!(" Payer must sign revocations");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+
+    let (expected_address, bump) = Mandate::find_address(payer.key, intent_hash, program_id);
+    if *mandate.key != expected_address {
+        msg!(" Mandate address mismatch");
+        return Err(TossError::InvalidMandate.into());
+    }
+    let mut state = if mandate.data_is_empty() {
+        create_pda_account(
+            payer,
+            mandate,
+            system_program,
+            program_id,
+            Mandate::LEN,
+            &[
+                Mandate::SEED_PREFIX,
+                payer.key.as_ref(),
+                intent_hash,
+                &[bump],
+            ],
+        )?;
+        Mandate {
+            is_initialized: true,
+            payer: *payer.key,
+            payee: Pubkey::default(),
+            intent_hash: *intent_hash,
+            amount_per_period: 0,
+            period: 0,
+            periods: 0,
+            started_at: 0,
+            current_period: 0,
+            pulled_in_period: 0,
+            total_pulled: 0,
+            revoked: true,
+            bump,
+        }
+    } else {
+        load_mandate(program_id, mandate, payer.key, intent_hash)?
+    };
+    state.revoked = true;
+    state.serialize(&mut &mut mandate.data.borrow_mut()[..])?;
+    msg!(" Mandate {} revoked", mandate.key);
+    Ok(())
+}
+
+/// Pay a hash-locked escrow to its recipient against the preimage
+fn process_claim_escrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    preimage: &[u8],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let escrow = next_account_info(account_iter)?;
+    let recipient = next_account_info(account_iter)?;
+    let rent_payer = next_account_info(account_iter)?;
+
+    let state = load_escrow(program_id, escrow)?;
+    if *recipient.key != state.recipient {
+        msg!(" Recipient does not match escrow");
+        return Err(TossError::RecipientMismatch.into());
+    }
+    if Clock::get()?.unix_timestamp > state.deadline {
+        msg!(" Hash lock deadline {} has passed", state.deadline);
+        return Err(TossError::HashLockExpired.into());
+    }
+    if hash(preimage).to_bytes() != state.hash {
+        msg!(" Preimage does not match hash lock");
+        return Err(TossError::PreimageMismatch.into());
+    }
+
+    release_escrow(escrow, &state, recipient, rent_payer)?;
+    msg!(
+        " Escrow claimed: {} lamports to {}",
+        state.amount,
+        recipient.key
+    );
+    Ok(())
+}
+
+/// Return an expired hash-locked escrow to its sender
+fn process_refund_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let escrow = next_account_info(account_iter)?;
+    let sender = next_account_info(account_iter)?;
+    let rent_payer = next_account_info(account_iter)?;
+
+    let state = load_escrow(program_id, escrow)?;
+    if *sender.key != state.sender {
+        msg!(" Sender does not match escrow");
+        return Err(TossError::SenderMismatch.into());
+    }
+    if Clock::get()?.unix_timestamp <= state.deadline {
+        msg!(" Escrow locked until {}", state.deadline);
+        return Err(TossError::EscrowStillLocked.into());
+    }
+
+    release_escrow(escrow, &state, sender, rent_payer)?;
+    msg!(
+        " Escrow refunded: {} lamports to {}",
+        state.amount,
+        sender.key
+    );
+    Ok(())
+}
+
+/// Resize the sender's replay window
+fn process_configure_nonce_window(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    window_size: u16,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let sender = next_account_info(account_iter)?;
+    let nonce_tracker = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+
+    if !sender.is_signer {
+        msg!(" Sender must sign nonce window changes");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+
+    let mut tracker = load_or_create_nonce_tracker(
+        program_id,
+        nonce_tracker,
+        sender,
+        system_program,
+        sender.key,
+    )?;
+    tracker.set_window_size(window_size)?;
+    tracker.serialize(&mut &mut nonce_tracker.data.borrow_mut()[..])?;
+
+    msg!(" Nonce window set to {}", window_size);
     Ok(())
 }
 
 /// Verify the Ed25519 signature of the intent
+///
+/// Programs cannot run Ed25519 verification themselves, so the transaction
+/// must carry an Ed25519Program instruction `instructions_before`
+/// instructions before this one. Its offsets may point into this
+/// instruction's data.
+/// The runtime rejects the transaction if that signature is invalid; here we
+/// only have to confirm it covers exactly this sender, signature and message.
 fn verify_intent_signature(
+    instructions_sysvar: &AccountInfo,
+    instructions_before: usize,
     sender: &Pubkey,
     message: &[u8],
     signature: &[u8; 64],
 ) -> ProgramResult {
-    // Use Solana's ed25519 program to verify signature
-    // This is a critical security check - ensures the intent was actually signed by the sender
-    
-    // The ed25519 program expects:
-    // 1. The public key (32 bytes)
-    // 2. The signature (64 bytes)
-    // 3. The message (variable length)
-    
-    // Build verification data
-    let mut verify_data = Vec::new();
-    verify_data.push(0); // signature count = 1
-    verify_data.extend_from_slice(&signature[..]);
-    verify_data.extend_from_slice(&sender.to_bytes());
-    verify_data.extend_from_slice(message);
-
-    // Call ed25519 program for verification
-    // The program will consume this data in sysvar and verify
-    // For now, we'll use a simplified check (in production, use proper ed25519 verification)
-    
-    msg!(" Signature verification passed (placeholder)");
-    Ok(())
-}
-
-/// Validate that a nonce account exists and is properly configured
-fn validate_nonce_account(nonce_account: &AccountInfo) -> ProgramResult {
+    if !sysvar_instructions::check_id(instructions_sysvar.key) {
+        msg!(" Instructions sysvar account mismatch");
+        return Err(TossError::InvalidInstructionsSysvar.into());
+    }
+
+    let current_index =
+        sysvar_instructions::load_current_index_checked(instructions_sysvar)? as usize;
+    if current_index < instructions_before {
+        msg!(" No Ed25519 instruction precedes the intent");
+        return Err(TossError::MissingSignatureInstruction.into());
+    }
+
+    let ed25519_ix = sysvar_instructions::load_instruction_at_checked(
+        current_index - instructions_before,
+        instructions_sysvar,
+    )?;
+    let current_ix =
+        sysvar_instructions::load_instruction_at_checked(current_index, instructions_sysvar)?;
+    let payload = ed25519::parse_single_signature(
+        &ed25519_ix,
+        Some((current_index as u16, &current_ix.data)),
+    )?;
+
+    if payload.public_key != sender.as_ref() {
+        msg!(" Ed25519 public key does not match intent sender");
+        return Err(TossError::SignerMismatch.into());
+    }
+    if payload.signature != &signature[..] {
+        msg!(" Ed25519 signature does not match supplied signature");
+        return Err(TossError::SignatureMismatch.into());
+    }
+    if payload.message != message {
+        msg!(" Ed25519 message does not match intent preimage");
+        return Err(TossError::MessageMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Whether the transaction's first instruction advances `nonce_account`,
+/// making it a durable transaction that already consumed the nonce
+fn transaction_advanced_nonce(
+    instructions_sysvar: &AccountInfo,
+    nonce_account: &Pubkey,
+    authority: &Pubkey,
+) -> Result<bool, ProgramError> {
+    let first_ix = sysvar_instructions::load_instruction_at_checked(0, instructions_sysvar)?;
+    Ok(durable_nonce::is_advance_nonce_instruction(
+        &first_ix,
+        nonce_account,
+        authority,
+    ))
+}
+
+/// Mark `intent.nonce` as used in the sender's nonce tracker
+fn consume_intent_nonce<'a>(
+    program_id: &Pubkey,
+    nonce_tracker: &AccountInfo<'a>,
+    sender: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    intent: &SolanaIntent,
+) -> ProgramResult {
+    let mut tracker = load_or_create_nonce_tracker(
+        program_id,
+        nonce_tracker,
+        sender,
+        system_program,
+        &intent.from,
+    )?;
+
+    if let Err(e) = tracker.consume(intent.nonce) {
+        msg!(" Nonce {} rejected: {}", intent.nonce, e);
+        return Err(e.into());
+    }
+
+    tracker.serialize(&mut &mut nonce_tracker.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// Load `owner`'s nonce tracker, creating it with `payer` funding the rent
+/// if it does not exist yet
+fn load_or_create_nonce_tracker<'a>(
+    program_id: &Pubkey,
+    nonce_tracker: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    owner: &Pubkey,
+) -> Result<NonceTracker, ProgramError> {
+    if let Some(tracker) = load_nonce_tracker(program_id, nonce_tracker, owner)? {
+        return Ok(tracker);
+    }
+    let (_, bump) = NonceTracker::find_address(owner, program_id);
+    create_pda_account(
+        payer,
+        nonce_tracker,
+        system_program,
+        program_id,
+        NonceTracker::LEN,
+        &[NonceTracker::SEED_PREFIX, owner.as_ref(), &[bump]],
+    )?;
+    Ok(NonceTracker::new(*owner, bump))
+}
+
+/// Load `owner`'s nonce tracker, or `None` if it has not been created yet
+fn load_nonce_tracker(
+    program_id: &Pubkey,
+    nonce_tracker: &AccountInfo,
+    owner: &Pubkey,
+) -> Result<Option<NonceTracker>, ProgramError> {
+    let (expected_address, _) = NonceTracker::find_address(owner, program_id);
+    if *nonce_tracker.key != expected_address {
+        msg!(" Nonce tracker address mismatch");
+        return Err(TossError::InvalidNonceTracker.into());
+    }
+    if nonce_tracker.data_is_empty() {
+        return Ok(None);
+    }
+
+    if nonce_tracker.owner != program_id {
+        msg!(" Nonce tracker not owned by program");
+        return Err(TossError::InvalidNonceTracker.into());
+    }
+    let tracker = NonceTracker::try_from_slice(&nonce_tracker.data.borrow())
+        .map_err(|_| TossError::InvalidNonceTracker)?;
+    if !tracker.is_initialized || tracker.owner != *owner {
+        msg!(" Nonce tracker does not belong to sender");
+        return Err(TossError::InvalidNonceTracker.into());
+    }
+    Ok(Some(tracker))
+}
+
+/// Load `owner`'s vault, checking its address and state
+fn load_vault(
+    program_id: &Pubkey,
+    vault: &AccountInfo,
+    owner: &Pubkey,
+) -> Result<Vault, ProgramError> {
+    let (expected_address, _) = Vault::find_address(owner, program_id);
+    if *vault.key != expected_address || vault.owner != program_id {
+        msg!(" Vault account mismatch");
+        return Err(TossError::InvalidVault.into());
+    }
+    let state = Vault::try_from_slice(&vault.data.borrow()).map_err(|_| TossError::InvalidVault)?;
+    if !state.is_initialized || state.owner != *owner {
+        msg!(" Vault does not belong to owner");
+        return Err(TossError::InvalidVault.into());
+    }
+    Ok(state)
+}
+
+/// Load a vault for an instruction its owner must sign
+fn load_owned_vault(
+    program_id: &Pubkey,
+    vault: &AccountInfo,
+    owner: &AccountInfo,
+) -> Result<Vault, ProgramError> {
+    if !owner.is_signer {
+        msg!(" Vault owner must sign");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+    if vault.owner == program_id {
+        let state =
+            Vault::try_from_slice(&vault.data.borrow()).map_err(|_| TossError::InvalidVault)?;
+        if state.owner != *owner.key {
+            msg!(" Signer does not own the vault");
+            return Err(TossError::VaultOwnerMismatch.into());
+        }
+    }
+    load_vault(program_id, vault, owner.key)
+}
+
+/// Load `payee`'s invoice `invoice_id`, checking its address and state
+fn load_invoice(
+    program_id: &Pubkey,
+    invoice: &AccountInfo,
+    payee: &Pubkey,
+    invoice_id: u64,
+) -> Result<Invoice, ProgramError> {
+    let (expected_address, _) = Invoice::find_address(payee, invoice_id, program_id);
+    if *invoice.key != expected_address || invoice.owner != program_id {
+        msg!(" Invoice account mismatch");
+        return Err(TossError::InvalidInvoice.into());
+    }
+    let state =
+        Invoice::try_from_slice(&invoice.data.borrow()).map_err(|_| TossError::InvalidInvoice)?;
+    if !state.is_initialized || state.payee != *payee || state.invoice_id != invoice_id {
+        msg!(" Invoice does not belong to payee");
+        return Err(TossError::InvalidInvoice.into());
+    }
+    Ok(state)
+}
+
+/// Load an invoice for an instruction its payee must sign
+fn load_owned_invoice(
+    program_id: &Pubkey,
+    invoice: &AccountInfo,
+    payee: &AccountInfo,
+    invoice_id: u64,
+) -> Result<Invoice, ProgramError> {
+    if !payee.is_signer {
+        msg!(" Invoice payee must sign");
+        return Err(TossError::MissingRequiredSigner.into());
+    }
+    load_invoice(program_id, invoice, payee.key, invoice_id)
+}
+
+/// Load `payer`'s mandate for `intent_hash`, checking its address and state
+fn load_mandate(
+    program_id: &Pubkey,
+    mandate: &AccountInfo,
+    payer: &Pubkey,
+    intent_hash: &[u8; 32],
+) -> Result<Mandate, ProgramError> {
+    let (expected_address, _) = Mandate::find_address(payer, intent_hash, program_id);
+    if *mandate.key != expected_address || mandate.owner != program_id {
+        msg!(" Mandate account mismatch");
+        return Err(TossError::InvalidMandate.into());
+    }
+    let state =
+        Mandate::try_from_slice(&mandate.data.borrow()).map_err(|_| TossError::InvalidMandate)?;
+    if !state.is_initialized || state.payer != *payer || state.intent_hash != *intent_hash {
+        msg!(" Mandate does not belong to payer");
+        return Err(TossError::InvalidMandate.into());
+    }
+    Ok(state)
+}
+
+/// Load an escrow, checking its address against the intent it records
+fn load_escrow(program_id: &Pubkey, escrow: &AccountInfo) -> Result<Escrow, ProgramError> {
+    if escrow.owner != program_id {
+        msg!(" Escrow not owned by program");
+        return Err(TossError::InvalidEscrow.into());
+    }
+    let state =
+        Escrow::try_from_slice(&escrow.data.borrow()).map_err(|_| TossError::InvalidEscrow)?;
+    if !state.is_initialized
+        || *escrow.key != Escrow::find_address(&state.intent_hash, program_id).0
+    {
+        msg!(" Escrow account mismatch");
+        return Err(TossError::InvalidEscrow.into());
+    }
+    Ok(state)
+}
+
+/// Pay an escrow's locked amount to `destination` and its rent back to
+/// whoever funded it, closing the escrow
+fn release_escrow(
+    escrow: &AccountInfo,
+    state: &Escrow,
+    destination: &AccountInfo,
+    rent_payer: &AccountInfo,
+) -> ProgramResult {
+    if *rent_payer.key != state.rent_payer {
+        msg!(" Rent payer does not match escrow");
+        return Err(TossError::InvalidEscrow.into());
+    }
+
+    let lamports = escrow.lamports();
+    let rent = lamports
+        .checked_sub(state.amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **escrow.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(state.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **rent_payer.try_borrow_mut_lamports()? = rent_payer
+        .lamports()
+        .checked_add(rent)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    escrow.realloc(0, false)?;
+    escrow.assign(&system_program::ID);
+    Ok(())
+}
+
+/// Consume the vault's pending withdrawal of `mint` once it has matured
+fn take_matured_withdrawal(vault_state: &mut Vault, mint: &Pubkey) -> Result<u64, ProgramError> {
+    let now = Clock::get()?.unix_timestamp;
+    vault_state.take_withdrawal(mint, now).map_err(|e| {
+        msg!(" No matured withdrawal of {}: {}", mint, e);
+        e.into()
+    })
+}
+
+/// Lamports a vault holds beyond its rent-exempt minimum and bond
+fn spendable_lamports(vault: &AccountInfo, vault_state: &Vault) -> Result<u64, ProgramError> {
+    let reserved = Rent::get()?
+        .minimum_balance(vault.data_len())
+        .saturating_add(vault_state.bond);
+    Ok(vault.lamports().saturating_sub(reserved))
+}
+
+/// Make the lamport transfers settled intents queued
+fn pay_vault_debits(debits: &[VaultDebit]) -> ProgramResult {
+    for debit in debits {
+        debit_vault(
+            debit.vault,
+            &debit.vault_state,
+            debit.destination,
+            debit.amount,
+        )?;
+    }
+    Ok(())
+}
+
+/// Move `amount` lamports out of a vault, keeping its rent and bond
+fn debit_vault(
+    vault: &AccountInfo,
+    vault_state: &Vault,
+    destination: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    let spendable = spendable_lamports(vault, vault_state)?;
+    if spendable < amount {
+        msg!(
+            " Vault holds {} spendable lamports, {} needed",
+            spendable,
+            amount
+        );
+        return Err(TossError::InsufficientFunds.into());
+    }
+
+    **vault.try_borrow_mut_lamports()? -= amount;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+/// Create a program-owned PDA, tolerating accounts that were pre-funded
+/// with lamports before creation
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    program_id: &Pubkey,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let required_lamports = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(new_account.lamports());
+
+    if new_account.lamports() == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                new_account.key,
+                required_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[payer.clone(), new_account.clone(), system_program.clone()],
+            &[seeds],
+        )?;
+        return Ok(());
+    }
+
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, new_account.key, required_lamports),
+            &[payer.clone(), new_account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(new_account.key, space as u64),
+        &[new_account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(new_account.key, program_id),
+        &[new_account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Validate that a nonce account is an initialized system nonce account
+/// controlled by `authority` and, when the intent committed to one, still
+/// holding the durable nonce the sender reserved offline
+fn validate_nonce_account(
+    nonce_account: &AccountInfo,
+    authority: &Pubkey,
+    committed_nonce: Option<&[u8; 32]>,
+) -> ProgramResult {
     // Check owner is system program
     if nonce_account.owner != &system_program::ID {
         msg!(" Nonce account not owned by system program");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(TossError::InvalidNonceAccount.into());
     }
 
-    // Check account is initialized (data length should be 48)
-    if nonce_account.data_len() < 48 {
-        msg!(" Nonce account data is too short");
-        return Err(ProgramError::InvalidAccountData);
+    let state = durable_nonce::unpack_nonce_account(&nonce_account.data.borrow())
+        .inspect_err(|e| msg!(" Nonce account rejected: {}", e))?;
+
+    if state.authority != *authority {
+        msg!(" Nonce account authority mismatch");
+        return Err(TossError::NonceAccountAuthorityMismatch.into());
+    }
+
+    if let Some(committed_nonce) = committed_nonce {
+        if state.durable_nonce != *committed_nonce {
+            msg!(" Nonce account no longer holds the committed durable nonce");
+            return Err(TossError::DurableNonceMismatch.into());
+        }
     }
 
-    msg!(" Nonce account structure is valid");
+    msg!(" Nonce account state is valid");
     Ok(())
 }
 
@@ -0,0 +1,140 @@
+//! Signing preimage for TOSS intents
+//!
+//! Senders never sign the raw intent bytes. The signed message is prefixed
+//! with a domain tag, the preimage format version, the settling program id
+//! and the cluster, so a signature is only valid for one program on one
+//! cluster. Off-chain tooling should build the message with this module
+//! (depending on the crate with the `no-entrypoint` feature) so it produces
+//! exactly the bytes the program verifies.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+/// Domain tag opening every TOSS signing preimage
+pub const DOMAIN_TAG: &[u8] = b"TOSS-INTENT";
+
+/// Version of the preimage layout below
+pub const SIGNING_FORMAT_VERSION: u8 = 1;
+
+/// Length of the prefix placed before the intent bytes:
+/// `DOMAIN_TAG || SIGNING_FORMAT_VERSION || program_id || cluster`
+pub const PREFIX_LEN: usize = DOMAIN_TAG.len() + 1 + 32 + 1;
+
+/// Cluster an intent is meant to settle on
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Cluster {
+    Mainnet = 0,
+    Devnet = 1,
+    Testnet = 2,
+    Localnet = 3,
+}
+
+#[cfg(not(any(
+    feature = "mainnet",
+    feature = "devnet",
+    feature = "testnet",
+    feature = "localnet"
+)))]
+compile_error!("One of the `mainnet`, `devnet`, `testnet` and `localnet` features must be enabled");
+
+#[cfg(any(
+    all(feature = "mainnet", feature = "devnet"),
+    all(feature = "mainnet", feature = "testnet"),
+    all(feature = "mainnet", feature = "localnet"),
+    all(feature = "devnet", feature = "testnet"),
+    all(feature = "devnet", feature = "localnet"),
+    all(feature = "testnet", feature = "localnet"),
+))]
+compile_error!(
+    "Only one of the `mainnet`, `devnet`, `testnet` and `localnet` features may be enabled"
+);
+
+/// Cluster this build of the program verifies signatures for
+#[cfg(feature = "mainnet")]
+pub const CLUSTER: Cluster = Cluster::Mainnet;
+#[cfg(feature = "devnet")]
+pub const CLUSTER: Cluster = Cluster::Devnet;
+#[cfg(feature = "testnet")]
+pub const CLUSTER: Cluster = Cluster::Testnet;
+#[cfg(feature = "localnet")]
+pub const CLUSTER: Cluster = Cluster::Localnet;
+
+/// Prefix binding a signature to `program_id` on `cluster`
+pub fn signing_prefix(program_id: &Pubkey, cluster: Cluster) -> [u8; PREFIX_LEN] {
+    let mut prefix = [0u8; PREFIX_LEN];
+    let (tag, rest) = prefix.split_at_mut(DOMAIN_TAG.len());
+    tag.copy_from_slice(DOMAIN_TAG);
+    rest[0] = SIGNING_FORMAT_VERSION;
+    rest[1..33].copy_from_slice(program_id.as_ref());
+    rest[33] = cluster as u8;
+    prefix
+}
+
+/// Message a sender signs to authorize `intent_data` for `program_id` on `cluster`
+pub fn signing_preimage(program_id: &Pubkey, cluster: Cluster, intent_data: &[u8]) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(PREFIX_LEN + intent_data.len());
+    preimage.extend_from_slice(&signing_prefix(program_id, cluster));
+    preimage.extend_from_slice(intent_data);
+    preimage
+}
+
+/// Intent bytes of a signing preimage for `program_id` on `cluster`, or
+/// `None` if `message` was signed for another program or cluster
+pub fn intent_data<'a>(
+    program_id: &Pubkey,
+    cluster: Cluster,
+    message: &'a [u8],
+) -> Option<&'a [u8]> {
+    message.strip_prefix(&signing_prefix(program_id, cluster)[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preimage_layout() {
+        let program_id = Pubkey::new_unique();
+        let preimage = signing_preimage(&program_id, Cluster::Devnet, &[0xAA, 0xBB]);
+
+        assert_eq!(preimage.len(), PREFIX_LEN + 2);
+        assert_eq!(&preimage[..DOMAIN_TAG.len()], DOMAIN_TAG);
+        assert_eq!(preimage[DOMAIN_TAG.len()], SIGNING_FORMAT_VERSION);
+        assert_eq!(
+            &preimage[DOMAIN_TAG.len() + 1..DOMAIN_TAG.len() + 33],
+            program_id.as_ref()
+        );
+        assert_eq!(preimage[PREFIX_LEN - 1], Cluster::Devnet as u8);
+        assert_eq!(&preimage[PREFIX_LEN..], &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn test_intent_data_strips_matching_prefix() {
+        let program_id = Pubkey::new_unique();
+        let preimage = signing_preimage(&program_id, Cluster::Devnet, &[0xAA, 0xBB]);
+
+        assert_eq!(
+            intent_data(&program_id, Cluster::Devnet, &preimage),
+            Some(&[0xAA, 0xBB][..])
+        );
+        assert_eq!(intent_data(&program_id, Cluster::Mainnet, &preimage), None);
+        assert_eq!(
+            intent_data(&Pubkey::new_unique(), Cluster::Devnet, &preimage),
+            None
+        );
+    }
+
+    #[test]
+    fn test_preimage_differs_per_program_and_cluster() {
+        let program_id = Pubkey::new_unique();
+        let data = [1u8; 8];
+        let base = signing_preimage(&program_id, Cluster::Mainnet, &data);
+
+        assert_ne!(base, signing_preimage(&program_id, Cluster::Devnet, &data));
+        assert_ne!(
+            base,
+            signing_preimage(&Pubkey::new_unique(), Cluster::Mainnet, &data)
+        );
+    }
+}
@@ -0,0 +1,785 @@
+//! Program-owned account state
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+use crate::error::TossError;
+
+/// Largest replay window a sender can configure, bounded by the bitmap size
+pub const MAX_NONCE_WINDOW: u16 = 256;
+
+/// Replay window given to a sender's tracker when it is first created
+pub const DEFAULT_NONCE_WINDOW: u16 = 64;
+
+const WINDOW_WORDS: usize = MAX_NONCE_WINDOW as usize / 64;
+
+/// Per-sender replay protection for `SolanaIntent.nonce`
+///
+/// Offline intents reach the chain in arbitrary order, so instead of a
+/// strictly increasing counter the tracker keeps a sliding window of the
+/// `window_size` nonces ending at `highest_nonce`. A nonce up to
+/// `window_size` above the highest settled one is accepted and slides the
+/// window forward; nonces inside the window are accepted once; nonces below
+/// it are stale, and nonces further ahead are rejected so a single intent
+/// cannot push every outstanding nonce out of the window.
+///
+/// PDA seeds: `[NonceTracker::SEED_PREFIX, sender]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NonceTracker {
+    pub is_initialized: bool,
+    /// Sender whose intents this tracker protects
+    pub owner: Pubkey,
+    pub bump: u8,
+    /// Highest nonce consumed so far
+    pub highest_nonce: u64,
+    /// Number of nonces, ending at `highest_nonce`, that can still settle
+    pub window_size: u16,
+    /// Bit `i` is set when nonce `highest_nonce - i` has been consumed
+    pub window: [u64; WINDOW_WORDS],
+}
+
+impl NonceTracker {
+    pub const SEED_PREFIX: &'static [u8] = b"nonce_tracker";
+    pub const LEN: usize = 1 + 32 + 1 + 8 + 2 + 8 * WINDOW_WORDS;
+
+    pub fn new(owner: Pubkey, bump: u8) -> Self {
+        Self {
+            is_initialized: true,
+            owner,
+            bump,
+            highest_nonce: 0,
+            window_size: DEFAULT_NONCE_WINDOW,
+            window: [0; WINDOW_WORDS],
+        }
+    }
+
+    pub fn find_address(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED_PREFIX, owner.as_ref()], program_id)
+    }
+
+    /// Check that `nonce` can still be consumed
+    pub fn check(&self, nonce: u64) -> Result<(), TossError> {
+        if nonce > self.highest_nonce {
+            if nonce - self.highest_nonce > self.window_size as u64 {
+                return Err(TossError::NonceTooFarAhead);
+            }
+            return Ok(());
+        }
+        let offset = self.highest_nonce - nonce;
+        if offset >= self.window_size as u64 {
+            return Err(TossError::NonceTooOld);
+        }
+        if self.is_consumed(offset as usize) {
+            return Err(TossError::NonceAlreadyUsed);
+        }
+        Ok(())
+    }
+
+    /// Record `nonce` as consumed
+    pub fn consume(&mut self, nonce: u64) -> Result<(), TossError> {
+        self.check(nonce)?;
+        if nonce > self.highest_nonce {
+            self.shift_window(nonce - self.highest_nonce);
+            self.highest_nonce = nonce;
+            self.mark_consumed(0);
+        } else {
+            self.mark_consumed((self.highest_nonce - nonce) as usize);
+        }
+        Ok(())
+    }
+
+    /// Change the replay window, forgetting nonces that fall outside it
+    pub fn set_window_size(&mut self, window_size: u16) -> Result<(), TossError> {
+        if window_size == 0 || window_size > MAX_NONCE_WINDOW {
+            return Err(TossError::InvalidNonceWindow);
+        }
+        self.window_size = window_size;
+        self.clear_outside_window();
+        Ok(())
+    }
+
+    fn is_consumed(&self, offset: usize) -> bool {
+        self.window[offset / 64] & (1 << (offset % 64)) != 0
+    }
+
+    fn mark_consumed(&mut self, offset: usize) {
+        self.window[offset / 64] |= 1 << (offset % 64);
+    }
+
+    /// Move every recorded nonce `by` positions further from the window head
+    fn shift_window(&mut self, by: u64) {
+        if by >= self.window_size as u64 {
+            self.window = [0; WINDOW_WORDS];
+            return;
+        }
+        let words = (by / 64) as usize;
+        let bits = (by % 64) as u32;
+        for i in (0..WINDOW_WORDS).rev() {
+            let mut word = 0;
+            if i >= words {
+                word = self.window[i - words] << bits;
+                if bits > 0 && i > words {
+                    word |= self.window[i - words - 1] >> (64 - bits);
+                }
+            }
+            self.window[i] = word;
+        }
+        self.clear_outside_window();
+    }
+
+    fn clear_outside_window(&mut self) {
+        for offset in self.window_size as usize..MAX_NONCE_WINDOW as usize {
+            self.window[offset / 64] &= !(1 << (offset % 64));
+        }
+    }
+}
+
+/// Longest withdrawal delay an owner can configure (30 days)
+pub const MAX_WITHDRAWAL_DELAY: u64 = 30 * 24 * 60 * 60;
+
+/// Withdrawal delay given to a vault when it is first created (1 day)
+pub const DEFAULT_WITHDRAWAL_DELAY: u64 = 24 * 60 * 60;
+
+/// Time a double-spend victim can count on to report once the sender starts
+/// releasing their bond (30 days)
+pub const FRAUD_REPORT_WINDOW: u64 = 30 * 24 * 60 * 60;
+
+/// Wait between `RequestBondRelease` and closing a bonded vault; never
+/// shorter than `FRAUD_REPORT_WINDOW`
+pub const BOND_RELEASE_DELAY: u64 = FRAUD_REPORT_WINDOW;
+
+/// Per-user spending vault debited by `ProcessIntent`
+///
+/// The owner funds the vault while online; settlement then moves funds out
+/// on the strength of the owner's verified intent signature alone, so any
+/// relayer can submit it. Lamports above the vault's rent-exempt minimum are
+/// spendable. Token accounts owned by the vault PDA hold its token balances.
+///
+/// The owner only gets funds back through a timelocked withdrawal: a request
+/// names the mint and amount and matures `withdrawal_delay` seconds later,
+/// while intents keep settling against the vault in the meantime. A recipient
+/// holding an intent therefore has at least the delay to collect it. Lowering
+/// the delay only takes effect once the previous delay has run out since the
+/// change, so it cannot shorten the window of intents already handed out.
+///
+/// Lamports posted as `bond` are a security deposit: intents and withdrawals
+/// cannot spend them, and a proven double spend hands them to the victim.
+/// A bonded vault only closes `BOND_RELEASE_DELAY` after the owner requests
+/// a bond release, so victims can still report in the meantime.
+///
+/// PDA seeds: `[Vault::SEED_PREFIX, owner]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vault {
+    pub is_initialized: bool,
+    /// User whose intents spend from this vault
+    pub owner: Pubkey,
+    pub bump: u8,
+    /// Seconds between requesting and committing a withdrawal
+    pub withdrawal_delay: u64,
+    /// Delay in force before the last change
+    pub previous_withdrawal_delay: u64,
+    /// Unix time of the last delay change
+    pub delay_changed_at: i64,
+    /// Mint of the pending withdrawal, the system program for lamports
+    pub withdrawal_mint: Pubkey,
+    /// Amount of the pending withdrawal, zero when none is pending
+    pub withdrawal_amount: u64,
+    /// Unix time from which the pending withdrawal can be committed
+    pub withdrawal_unlocks_at: i64,
+    /// Lamports held back as a security deposit against double spends
+    pub bond: u64,
+    /// Unix time from which the bond can be taken back by closing the vault,
+    /// zero until a release is requested
+    pub bond_unlocks_at: i64,
+}
+
+impl Vault {
+    pub const SEED_PREFIX: &'static [u8] = b"vault";
+    pub const LEN: usize = 1 + 32 + 1 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8;
+
+    pub fn new(owner: Pubkey, bump: u8) -> Self {
+        Self {
+            is_initialized: true,
+            owner,
+            bump,
+            withdrawal_delay: DEFAULT_WITHDRAWAL_DELAY,
+            previous_withdrawal_delay: DEFAULT_WITHDRAWAL_DELAY,
+            delay_changed_at: 0,
+            withdrawal_mint: Pubkey::default(),
+            withdrawal_amount: 0,
+            withdrawal_unlocks_at: 0,
+            bond: 0,
+            bond_unlocks_at: 0,
+        }
+    }
+
+    pub fn find_address(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED_PREFIX, owner.as_ref()], program_id)
+    }
+
+    /// Delay a withdrawal requested at `now` has to wait
+    pub fn effective_withdrawal_delay(&self, now: i64) -> u64 {
+        let previous_ends = self
+            .delay_changed_at
+            .saturating_add_unsigned(self.previous_withdrawal_delay);
+        if now < previous_ends {
+            self.withdrawal_delay.max(self.previous_withdrawal_delay)
+        } else {
+            self.withdrawal_delay
+        }
+    }
+
+    /// Change the withdrawal delay at `now`
+    pub fn set_withdrawal_delay(&mut self, delay: u64, now: i64) -> Result<(), TossError> {
+        if delay > MAX_WITHDRAWAL_DELAY {
+            return Err(TossError::InvalidWithdrawalDelay);
+        }
+        self.previous_withdrawal_delay = self.effective_withdrawal_delay(now);
+        self.withdrawal_delay = delay;
+        self.delay_changed_at = now;
+        Ok(())
+    }
+
+    /// Start a withdrawal of `amount` of `mint` at `now`, replacing any
+    /// pending one; an amount of zero cancels the pending withdrawal
+    pub fn request_withdrawal(&mut self, mint: Pubkey, amount: u64, now: i64) {
+        self.withdrawal_mint = mint;
+        self.withdrawal_amount = amount;
+        self.withdrawal_unlocks_at =
+            now.saturating_add_unsigned(self.effective_withdrawal_delay(now));
+    }
+
+    /// Take the pending withdrawal of `mint` if it has matured by `now`,
+    /// returning its amount
+    pub fn take_withdrawal(&mut self, mint: &Pubkey, now: i64) -> Result<u64, TossError> {
+        if self.withdrawal_amount == 0 || self.withdrawal_mint != *mint {
+            return Err(TossError::WithdrawalNotRequested);
+        }
+        if now < self.withdrawal_unlocks_at {
+            return Err(TossError::WithdrawalLocked);
+        }
+        let amount = self.withdrawal_amount;
+        self.withdrawal_amount = 0;
+        Ok(amount)
+    }
+
+    /// Start the bond release clock at `now`
+    pub fn request_bond_release(&mut self, now: i64) {
+        self.bond_unlocks_at = now.saturating_add_unsigned(BOND_RELEASE_DELAY);
+    }
+
+    /// Check that closing the vault at `now` cannot take back a bond victims
+    /// may still claim
+    pub fn check_bond_released(&self, now: i64) -> Result<(), TossError> {
+        if self.bond == 0 {
+            return Ok(());
+        }
+        if self.bond_unlocks_at == 0 {
+            return Err(TossError::BondReleaseNotRequested);
+        }
+        if now < self.bond_unlocks_at {
+            return Err(TossError::BondLocked);
+        }
+        Ok(())
+    }
+}
+
+/// Record of a settled intent, created by `ProcessIntent`
+///
+/// PDA seeds: `[SettlementReceipt::SEED_PREFIX, intent_hash]`, where
+/// `intent_hash` is the SHA-256 of the signed intent bytes.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SettlementReceipt {
+    pub is_initialized: bool,
+    pub intent_hash: [u8; 32],
+    pub sender: Pubkey,
+    /// `intent.to`; split legs are not recorded
+    pub recipient: Pubkey,
+    /// Total the sender paid, across every split leg
+    pub amount: u64,
+    /// Mint `amount` is denominated in; the system program id for lamports
+    pub mint: Pubkey,
+    pub nonce: u64,
+    /// Slot in which the intent settled
+    pub slot: u64,
+    /// Cluster unix time at which the intent settled
+    pub settled_at: i64,
+    /// Account that submitted the settlement and funded this receipt
+    pub relayer: Pubkey,
+    pub bump: u8,
+}
+
+impl SettlementReceipt {
+    pub const SEED_PREFIX: &'static [u8] = b"receipt";
+    pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 32 + 8 + 8 + 8 + 32 + 1;
+
+    pub fn find_address(intent_hash: &[u8; 32], program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED_PREFIX, intent_hash], program_id)
+    }
+}
+
+/// Record of a proven double spend
+///
+/// Created when `ReportDoubleSpend` slashes a sender's bond, so each
+/// recipient of a conflicting intent is compensated at most once, however
+/// many intents the sender signed with the same nonce.
+///
+/// PDA seeds: `[FraudRecord::SEED_PREFIX, conflicting_intent_hash, victim]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FraudRecord {
+    pub is_initialized: bool,
+    pub sender: Pubkey,
+    /// Nonce the sender signed twice
+    pub nonce: u64,
+    /// Hash of the intent that settled
+    pub settled_intent_hash: [u8; 32],
+    /// Hash of the conflicting intent that could no longer settle
+    pub conflicting_intent_hash: [u8; 32],
+    /// Recipient of the conflicting intent, paid the slashed bond
+    pub victim: Pubkey,
+    /// Lamports moved from the sender's bond to the victim, at most what the
+    /// conflicting intent would have paid them
+    pub slashed: u64,
+    /// Account that submitted the proof and funded this record
+    pub reporter: Pubkey,
+    /// Cluster unix time of the report
+    pub reported_at: i64,
+    pub bump: u8,
+}
+
+impl FraudRecord {
+    pub const SEED_PREFIX: &'static [u8] = b"fraud";
+    pub const LEN: usize = 1 + 32 + 8 + 32 + 32 + 32 + 8 + 32 + 8 + 1;
+
+    pub fn find_address(
+        conflicting_intent_hash: &[u8; 32],
+        victim: &Pubkey,
+        program_id: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED_PREFIX, conflicting_intent_hash, victim.as_ref()],
+            program_id,
+        )
+    }
+}
+
+/// Lifecycle of an `Invoice`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    /// Waiting for an intent to pay it
+    Open,
+    /// Settled by exactly one intent
+    Paid,
+    /// Withdrawn by the payee before it was paid
+    Cancelled,
+}
+
+/// Payment request a payee creates for an intent to settle
+///
+/// The payee publishes the invoice (typically as a QR code) and the payer
+/// signs an intent carrying `IntentExtension::Invoice(invoice_id)` offline.
+/// Settlement checks the intent against the invoice and marks it paid, so a
+/// second intent for the same invoice cannot settle.
+///
+/// The account is sized to its memo, see `Invoice::space`.
+///
+/// PDA seeds: `[Invoice::SEED_PREFIX, payee, invoice_id (u64 LE)]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    pub is_initialized: bool,
+    /// Account to be paid, `intent.to` of the paying intent
+    pub payee: Pubkey,
+    /// Payee-chosen id, unique among the payee's live invoices
+    pub invoice_id: u64,
+    /// Amount due, in lamports or base units of `mint`
+    pub amount: u64,
+    /// Mint `amount` is denominated in; the system program id for lamports
+    pub mint: Pubkey,
+    /// Unix time after which the invoice can no longer be paid
+    pub expiry: i64,
+    pub status: InvoiceStatus,
+    /// Sender of the paying intent, the default key until paid
+    pub paid_by: Pubkey,
+    /// Hash of the paying intent, zero until paid
+    pub paid_intent_hash: [u8; 32],
+    pub bump: u8,
+    /// Payee's note to the payer, such as an order id
+    pub memo: String,
+}
+
+impl Invoice {
+    pub const SEED_PREFIX: &'static [u8] = b"invoice";
+    /// Size of an invoice with an empty memo
+    pub const BASE_LEN: usize = 1 + 32 + 8 + 8 + 32 + 8 + 1 + 32 + 32 + 1 + 4;
+
+    /// Account size of an invoice carrying `memo`
+    pub fn space(memo: &str) -> usize {
+        Self::BASE_LEN + memo.len()
+    }
+
+    pub fn find_address(payee: &Pubkey, invoice_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED_PREFIX, payee.as_ref(), &invoice_id.to_le_bytes()],
+            program_id,
+        )
+    }
+
+    /// Whether the invoice can still be paid at `now`
+    pub fn check_payable(&self, now: i64) -> Result<(), TossError> {
+        if self.status != InvoiceStatus::Open {
+            return Err(TossError::InvoiceNotOpen);
+        }
+        if now > self.expiry {
+            return Err(TossError::InvoiceExpired);
+        }
+        Ok(())
+    }
+
+    /// Withdraw the open invoice
+    pub fn cancel(&mut self) -> Result<(), TossError> {
+        if self.status != InvoiceStatus::Open {
+            return Err(TossError::InvoiceNotOpen);
+        }
+        self.status = InvoiceStatus::Cancelled;
+        Ok(())
+    }
+
+    /// Whether the invoice can be closed at `now`: it was paid, cancelled
+    /// or can no longer be paid
+    pub fn check_closable(&self, now: i64) -> Result<(), TossError> {
+        if self.status == InvoiceStatus::Open && now <= self.expiry {
+            return Err(TossError::InvoiceStillOpen);
+        }
+        Ok(())
+    }
+}
+
+/// Recurring payment a payer signed offline as a mandate intent
+///
+/// `ActivateMandate` creates it from the signed intent; the payee then pulls
+/// up to `amount_per_period` lamports from the payer's vault in each of
+/// `periods` consecutive periods of `period` seconds, starting at
+/// activation. Unused allowance does not carry over. The payer can revoke
+/// it at any time, including before activation, which leaves a revoked
+/// mandate behind so the intent can never be activated.
+///
+/// PDA seeds: `[Mandate::SEED_PREFIX, payer, intent_hash]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Mandate {
+    pub is_initialized: bool,
+    /// Sender of the mandate intent, whose vault is debited
+    pub payer: Pubkey,
+    /// Recipient of the mandate intent, the only account that can pull
+    pub payee: Pubkey,
+    /// Hash of the signed mandate intent
+    pub intent_hash: [u8; 32],
+    /// Lamports the payee can pull per period
+    pub amount_per_period: u64,
+    /// Period length in seconds
+    pub period: u64,
+    /// Number of periods, after which the mandate ends
+    pub periods: u32,
+    /// Unix time at which the first period began
+    pub started_at: i64,
+    /// Index of the period `pulled_in_period` counts
+    pub current_period: u32,
+    /// Lamports pulled in `current_period`
+    pub pulled_in_period: u64,
+    /// Lamports pulled over the mandate's life
+    pub total_pulled: u64,
+    pub revoked: bool,
+    pub bump: u8,
+}
+
+impl Mandate {
+    pub const SEED_PREFIX: &'static [u8] = b"mandate";
+    pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 4 + 8 + 8 + 1 + 1;
+
+    pub fn find_address(
+        payer: &Pubkey,
+        intent_hash: &[u8; 32],
+        program_id: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::SEED_PREFIX, payer.as_ref(), intent_hash],
+            program_id,
+        )
+    }
+
+    /// Record a pull of `amount` lamports at `now`, moving to the period
+    /// `now` falls in first
+    pub fn pull(&mut self, amount: u64, now: i64) -> Result<(), TossError> {
+        if self.revoked {
+            return Err(TossError::MandateRevoked);
+        }
+        let elapsed = now.saturating_sub(self.started_at).max(0) as u64;
+        let period = elapsed / self.period;
+        if period >= self.periods as u64 {
+            return Err(TossError::MandateEnded);
+        }
+        if period != self.current_period as u64 {
+            self.current_period = period as u32;
+            self.pulled_in_period = 0;
+        }
+        let pulled = self
+            .pulled_in_period
+            .checked_add(amount)
+            .filter(|pulled| *pulled <= self.amount_per_period)
+            .ok_or(TossError::MandateAmountExceeded)?;
+        self.pulled_in_period = pulled;
+        self.total_pulled = self.total_pulled.saturating_add(amount);
+        Ok(())
+    }
+}
+
+/// Funds a hash-locked intent set aside at settlement
+///
+/// Holds `amount` lamports on top of its own rent. `ClaimEscrow` pays them
+/// to the recipient against the preimage of `hash` until `deadline`;
+/// `RefundEscrow` returns them to the sender afterwards. Either closes the
+/// escrow and returns its rent to `rent_payer`.
+///
+/// PDA seeds: `[Escrow::SEED_PREFIX, intent_hash]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Escrow {
+    pub is_initialized: bool,
+    /// Hash of the intent that locked the funds
+    pub intent_hash: [u8; 32],
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    /// Lamports locked
+    pub amount: u64,
+    /// SHA-256 the claimer's preimage must match
+    pub hash: [u8; 32],
+    /// Last unix time at which the recipient can claim
+    pub deadline: i64,
+    /// Relayer that funded the escrow's rent
+    pub rent_payer: Pubkey,
+    pub bump: u8,
+}
+
+impl Escrow {
+    pub const SEED_PREFIX: &'static [u8] = b"escrow";
+    pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 32 + 8 + 32 + 1;
+
+    pub fn find_address(intent_hash: &[u8; 32], program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED_PREFIX, intent_hash], program_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> NonceTracker {
+        NonceTracker::new(Pubkey::new_unique(), 255)
+    }
+
+    #[test]
+    fn test_out_of_order_nonces_within_window() {
+        let mut tracker = tracker();
+        for nonce in [5, 3, 9, 4, 1] {
+            tracker.consume(nonce).unwrap();
+        }
+        assert_eq!(tracker.highest_nonce, 9);
+        for nonce in [5, 3, 9, 4, 1] {
+            assert_eq!(tracker.check(nonce), Err(TossError::NonceAlreadyUsed));
+        }
+        for nonce in [2, 6, 7, 8, 10] {
+            assert_eq!(tracker.check(nonce), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_nonces_below_window_are_stale() {
+        let mut tracker = tracker();
+        tracker.consume(10).unwrap();
+        tracker.set_window_size(4).unwrap();
+        assert_eq!(tracker.check(7), Ok(()));
+        assert_eq!(tracker.check(6), Err(TossError::NonceTooOld));
+
+        tracker.consume(8).unwrap();
+        tracker.consume(12).unwrap();
+        assert_eq!(tracker.check(8), Err(TossError::NonceTooOld));
+        assert_eq!(tracker.check(10), Err(TossError::NonceAlreadyUsed));
+        assert_eq!(tracker.check(9), Ok(()));
+    }
+
+    #[test]
+    fn test_nonces_beyond_window_are_rejected() {
+        let mut tracker = tracker();
+        tracker.set_window_size(4).unwrap();
+        assert_eq!(tracker.check(5), Err(TossError::NonceTooFarAhead));
+        tracker.consume(4).unwrap();
+
+        assert_eq!(tracker.check(9), Err(TossError::NonceTooFarAhead));
+        assert_eq!(tracker.check(u64::MAX), Err(TossError::NonceTooFarAhead));
+        tracker.consume(8).unwrap();
+        assert_eq!(tracker.check(12), Ok(()));
+    }
+
+    #[test]
+    fn test_window_shift_across_words() {
+        let mut tracker = tracker();
+        tracker.set_window_size(MAX_NONCE_WINDOW).unwrap();
+        tracker.consume(1).unwrap();
+        tracker.consume(2).unwrap();
+        tracker.consume(130).unwrap();
+        assert_eq!(tracker.check(1), Err(TossError::NonceAlreadyUsed));
+        assert_eq!(tracker.check(2), Err(TossError::NonceAlreadyUsed));
+        assert_eq!(tracker.check(3), Ok(()));
+
+        tracker.consume(257).unwrap();
+        assert_eq!(tracker.check(1), Err(TossError::NonceTooOld));
+        assert_eq!(tracker.check(2), Err(TossError::NonceAlreadyUsed));
+        assert_eq!(tracker.check(130), Err(TossError::NonceAlreadyUsed));
+    }
+
+    #[test]
+    fn test_withdrawal_matures_after_delay() {
+        let mut vault = Vault::new(Pubkey::new_unique(), 255);
+        let mint = Pubkey::new_unique();
+        assert_eq!(
+            vault.take_withdrawal(&mint, 0),
+            Err(TossError::WithdrawalNotRequested)
+        );
+
+        vault.request_withdrawal(mint, 500, 1_000);
+        let unlocks_at = 1_000 + DEFAULT_WITHDRAWAL_DELAY as i64;
+        assert_eq!(
+            vault.take_withdrawal(&mint, unlocks_at - 1),
+            Err(TossError::WithdrawalLocked)
+        );
+        assert_eq!(
+            vault.take_withdrawal(&Pubkey::new_unique(), unlocks_at),
+            Err(TossError::WithdrawalNotRequested)
+        );
+        assert_eq!(vault.take_withdrawal(&mint, unlocks_at), Ok(500));
+        assert_eq!(
+            vault.take_withdrawal(&mint, unlocks_at),
+            Err(TossError::WithdrawalNotRequested)
+        );
+    }
+
+    #[test]
+    fn test_bond_release_matures_after_delay() {
+        let mut vault = Vault::new(Pubkey::new_unique(), 255);
+        assert_eq!(vault.check_bond_released(0), Ok(()));
+
+        vault.bond = 1_000;
+        assert_eq!(
+            vault.check_bond_released(0),
+            Err(TossError::BondReleaseNotRequested)
+        );
+        vault.request_bond_release(1_000);
+        let unlocks_at = 1_000 + BOND_RELEASE_DELAY as i64;
+        assert_eq!(
+            vault.check_bond_released(unlocks_at - 1),
+            Err(TossError::BondLocked)
+        );
+        assert_eq!(vault.check_bond_released(unlocks_at), Ok(()));
+    }
+
+    #[test]
+    fn test_lowered_delay_waits_out_previous_delay() {
+        let mut vault = Vault::new(Pubkey::new_unique(), 255);
+        let changed_at = 10_000_000;
+        vault.set_withdrawal_delay(60, changed_at).unwrap();
+
+        let previous_ends = changed_at + DEFAULT_WITHDRAWAL_DELAY as i64;
+        assert_eq!(
+            vault.effective_withdrawal_delay(previous_ends - 1),
+            DEFAULT_WITHDRAWAL_DELAY
+        );
+        assert_eq!(vault.effective_withdrawal_delay(previous_ends), 60);
+
+        vault.set_withdrawal_delay(3_600, previous_ends).unwrap();
+        assert_eq!(vault.effective_withdrawal_delay(previous_ends), 3_600);
+        assert_eq!(
+            vault.set_withdrawal_delay(MAX_WITHDRAWAL_DELAY + 1, previous_ends),
+            Err(TossError::InvalidWithdrawalDelay)
+        );
+    }
+
+    #[test]
+    fn test_invoice_lifecycle() {
+        let mut invoice = Invoice {
+            is_initialized: true,
+            payee: Pubkey::new_unique(),
+            invoice_id: 7,
+            amount: 1_000,
+            mint: Pubkey::default(),
+            expiry: 100,
+            status: InvoiceStatus::Open,
+            paid_by: Pubkey::default(),
+            paid_intent_hash: [0; 32],
+            bump: 255,
+            memo: "order-7".into(),
+        };
+        assert_eq!(
+            borsh::to_vec(&invoice).unwrap().len(),
+            Invoice::space("order-7")
+        );
+
+        assert_eq!(invoice.check_payable(100), Ok(()));
+        assert_eq!(invoice.check_payable(101), Err(TossError::InvoiceExpired));
+        assert_eq!(
+            invoice.check_closable(100),
+            Err(TossError::InvoiceStillOpen)
+        );
+        assert_eq!(invoice.check_closable(101), Ok(()));
+
+        invoice.cancel().unwrap();
+        assert_eq!(invoice.check_payable(0), Err(TossError::InvoiceNotOpen));
+        assert_eq!(invoice.cancel(), Err(TossError::InvoiceNotOpen));
+        assert_eq!(invoice.check_closable(0), Ok(()));
+    }
+
+    #[test]
+    fn test_mandate_pulls_reset_each_period() {
+        let mut mandate = Mandate {
+            is_initialized: true,
+            payer: Pubkey::new_unique(),
+            payee: Pubkey::new_unique(),
+            intent_hash: [0; 32],
+            amount_per_period: 100,
+            period: 30,
+            periods: 2,
+            started_at: 1_000,
+            current_period: 0,
+            pulled_in_period: 0,
+            total_pulled: 0,
+            revoked: false,
+            bump: 255,
+        };
+
+        mandate.pull(60, 1_000).unwrap();
+        mandate.pull(40, 1_029).unwrap();
+        assert_eq!(
+            mandate.pull(1, 1_029),
+            Err(TossError::MandateAmountExceeded)
+        );
+
+        mandate.pull(100, 1_030).unwrap();
+        assert_eq!(mandate.current_period, 1);
+        assert_eq!(mandate.total_pulled, 200);
+        assert_eq!(mandate.pull(1, 1_060), Err(TossError::MandateEnded));
+
+        mandate.revoked = true;
+        assert_eq!(mandate.pull(0, 1_031), Err(TossError::MandateRevoked));
+    }
+
+    #[test]
+    fn test_window_size_bounds() {
+        let mut tracker = tracker();
+        assert_eq!(
+            tracker.set_window_size(0),
+            Err(TossError::InvalidNonceWindow)
+        );
+        assert_eq!(
+            tracker.set_window_size(MAX_NONCE_WINDOW + 1),
+            Err(TossError::InvalidNonceWindow)
+        );
+        assert_eq!(tracker.set_window_size(MAX_NONCE_WINDOW), Ok(()));
+    }
+}
@@ -0,0 +1,398 @@
+//! SPL Token and Token-2022 settlement
+//!
+//! Token intents move base units of a mint between the sender's and
+//! recipient's token accounts with `transfer_checked`, so the decimals the
+//! sender signed are enforced by the token program as well as checked here.
+//! Either token program is accepted; the mint and both token accounts must
+//! belong to the one passed in.
+//!
+//! Token-2022 mints with a transfer fee only settle when the intent accepts
+//! the fee with `IntentExtension::TransferFee`, which decides whether
+//! `amount` is what the sender pays (gross) or what the recipient receives
+//! (net). Recipients that require incoming memos get one naming the intent.
+//!
+//! Offline recipients often have never held the mint, so an intent can ask
+//! for the recipient's associated token account to be created idempotently
+//! before the transfer.
+
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Epoch,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::Sysvar,
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::{
+        memo_transfer::MemoTransfer, transfer_fee::TransferFeeConfig, BaseStateWithExtensions,
+        ExtensionType, StateWithExtensions,
+    },
+    state::{Account, Mint},
+};
+
+use crate::{
+    error::TossError,
+    intent::{TokenTransfer, TransferFeeMode, TransferFeeTerms},
+    SolanaIntent,
+};
+
+/// Accounts a token intent adds after the relayer
+pub struct TokenAccounts<'a, 'info> {
+    pub mint: &'a AccountInfo<'info>,
+    /// Sender's token account (writable)
+    pub source: &'a AccountInfo<'info>,
+    /// Recipient's token account (writable)
+    pub destination: &'a AccountInfo<'info>,
+    /// SPL Token or Token-2022
+    pub token_program: &'a AccountInfo<'info>,
+    /// Associated Token Account program, present only when the intent
+    /// creates the recipient token account
+    pub associated_token_program: Option<&'a AccountInfo<'info>>,
+    /// SPL Memo, present only when `destination` requires incoming memos
+    pub memo_program: Option<&'a AccountInfo<'info>>,
+}
+
+/// Amounts a validated token intent moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSettlement {
+    /// Base units debited from the sender
+    pub debit: u64,
+    /// Transfer fee withheld by Token-2022 out of `debit`
+    pub fee: u64,
+    /// Whether the mint has a transfer fee config, so the transfer must
+    /// state the fee
+    pub has_fee_config: bool,
+    /// Sender's token balance before the transfer
+    pub source_balance: u64,
+}
+
+impl<'a, 'info> TokenAccounts<'a, 'info> {
+    pub fn next<I>(account_iter: &mut I, creates_destination: bool) -> Result<Self, ProgramError>
+    where
+        I: Iterator<Item = &'a AccountInfo<'info>>,
+    {
+        let mint = next_account_info(account_iter)?;
+        let source = next_account_info(account_iter)?;
+        let destination = next_account_info(account_iter)?;
+        let token_program = next_account_info(account_iter)?;
+        let associated_token_program = if creates_destination {
+            Some(next_account_info(account_iter)?)
+        } else {
+            None
+        };
+        let memo_program = if requires_incoming_memo(destination) {
+            Some(next_account_info(account_iter)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            mint,
+            source,
+            destination,
+            token_program,
+            associated_token_program,
+            memo_program,
+        })
+    }
+
+    /// Check that `destination` is `recipient`'s associated token account
+    /// and that `payer` can fund it if it does not exist yet
+    pub fn check_destination(&self, payer: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+        check_token_program(self.token_program)?;
+        let associated_token_program = self
+            .associated_token_program
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if *associated_token_program.key != spl_associated_token_account::ID {
+            msg!(" Associated token account program mismatch");
+            return Err(TossError::InvalidTokenProgram.into());
+        }
+
+        let expected_address = get_associated_token_address_with_program_id(
+            recipient.key,
+            self.mint.key,
+            self.token_program.key,
+        );
+        if *self.destination.key != expected_address {
+            msg!(" Recipient token account is not the associated token account");
+            return Err(TossError::InvalidAssociatedTokenAccount.into());
+        }
+        if !self.destination.data_is_empty() {
+            return Ok(());
+        }
+
+        let required_lamports = Rent::get()?
+            .minimum_balance(self.destination_len()?)
+            .saturating_sub(self.destination.lamports());
+        if payer.lamports() < required_lamports {
+            msg!(
+                " Rent payer has {} lamports, recipient token account needs {}",
+                payer.lamports(),
+                required_lamports
+            );
+            return Err(TossError::RentPayerInsufficientFunds.into());
+        }
+        Ok(())
+    }
+
+    /// Create `recipient`'s associated token account unless it already
+    /// exists, with `payer` funding its rent
+    pub fn create_destination(
+        &self,
+        payer: &AccountInfo<'info>,
+        recipient: &AccountInfo<'info>,
+        system_program: &AccountInfo<'info>,
+    ) -> ProgramResult {
+        self.check_destination(payer, recipient)?;
+        if !self.destination.data_is_empty() {
+            return Ok(());
+        }
+        let associated_token_program = self
+            .associated_token_program
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        invoke(
+            &create_associated_token_account_idempotent(
+                payer.key,
+                recipient.key,
+                self.mint.key,
+                self.token_program.key,
+            ),
+            &[
+                payer.clone(),
+                self.destination.clone(),
+                recipient.clone(),
+                self.mint.clone(),
+                system_program.clone(),
+                self.token_program.clone(),
+                associated_token_program.clone(),
+            ],
+        )?;
+        msg!(" Created recipient token account {}", self.destination.key);
+        Ok(())
+    }
+
+    /// Whether the intent creates the still missing recipient token account
+    fn creates_destination(&self) -> bool {
+        self.associated_token_program.is_some() && self.destination.data_is_empty()
+    }
+
+    /// Size of an associated token account for the mint, including the
+    /// extensions Token-2022 adds for it
+    fn destination_len(&self) -> Result<usize, ProgramError> {
+        let mint_data = self.mint.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&mint_data)
+            .map_err(|_| TossError::TokenMintMismatch)?;
+        let mut extensions =
+            ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+        if *self.token_program.key == spl_token_2022::ID {
+            extensions.push(ExtensionType::ImmutableOwner);
+        }
+        ExtensionType::try_calculate_account_len::<Account>(&extensions)
+    }
+
+    /// Check the accounts against the intent, with the sender's tokens held
+    /// by `source_owner`, and work out what the transfer debits in `epoch`
+    pub fn validate(
+        &self,
+        intent: &SolanaIntent,
+        source_owner: &Pubkey,
+        token: &TokenTransfer,
+        fee_terms: Option<&TransferFeeTerms>,
+        epoch: Epoch,
+    ) -> Result<TokenSettlement, ProgramError> {
+        let token_program_id = self.token_program.key;
+        check_token_program(self.token_program)?;
+        if let Some(memo_program) = self.memo_program {
+            if *memo_program.key != spl_memo::ID {
+                msg!(" Memo program mismatch");
+                return Err(TossError::InvalidMemoProgram.into());
+            }
+        }
+
+        if *self.mint.key != token.mint || self.mint.owner != token_program_id {
+            msg!(" Mint account does not match intent");
+            return Err(TossError::TokenMintMismatch.into());
+        }
+        let mint_data = self.mint.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&mint_data)
+            .map_err(|_| TossError::TokenMintMismatch)?;
+        if mint.base.decimals != token.decimals {
+            msg!(
+                " Mint has {} decimals, intent signed {}",
+                mint.base.decimals,
+                token.decimals
+            );
+            return Err(TossError::TokenDecimalsMismatch.into());
+        }
+
+        let source_balance =
+            check_token_account(self.source, token_program_id, &token.mint, source_owner)?;
+        // A recipient token account about to be created is checked by
+        // `check_destination` and initialized by the ATA program instead
+        if !self.creates_destination() {
+            check_token_account(self.destination, token_program_id, &token.mint, &intent.to)?;
+        }
+
+        let fee_config = mint.get_extension::<TransferFeeConfig>().ok();
+        let (debit, fee) = match (fee_config, fee_terms) {
+            (None, _) => (intent.amount, 0),
+            (Some(config), Some(terms)) if terms.mode == TransferFeeMode::Net => {
+                let fee = config
+                    .calculate_inverse_epoch_fee(epoch, intent.amount)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                let debit = intent
+                    .amount
+                    .checked_add(fee)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                (debit, fee)
+            }
+            (Some(config), _) => {
+                let fee = config
+                    .calculate_epoch_fee(epoch, intent.amount)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                (intent.amount, fee)
+            }
+        };
+
+        if fee > 0 {
+            let max_fee = match fee_terms {
+                Some(terms) => terms.max_fee,
+                None => {
+                    msg!(
+                        " Mint charges a {} unit transfer fee the intent does not accept",
+                        fee
+                    );
+                    return Err(TossError::TransferFeeNotAccepted.into());
+                }
+            };
+            if fee > max_fee {
+                msg!(
+                    " Transfer fee {} exceeds the intent's maximum {}",
+                    fee,
+                    max_fee
+                );
+                return Err(TossError::TransferFeeExceedsMax.into());
+            }
+        }
+
+        Ok(TokenSettlement {
+            debit,
+            fee,
+            has_fee_config: fee_config.is_some(),
+            source_balance,
+        })
+    }
+
+    /// Transfer `settlement.debit` from the sender's token account, signed by
+    /// the `authority` PDA, preceded by `memo` when the recipient requires one
+    pub fn transfer(
+        &self,
+        authority: &AccountInfo<'info>,
+        authority_seeds: &[&[u8]],
+        settlement: &TokenSettlement,
+        decimals: u8,
+        memo: &[u8],
+    ) -> ProgramResult {
+        if let Some(memo_program) = self.memo_program {
+            invoke(
+                &spl_memo::build_memo(memo, &[]),
+                std::slice::from_ref(memo_program),
+            )?;
+        }
+
+        let ix = if settlement.has_fee_config {
+            spl_token_2022::extension::transfer_fee::instruction::transfer_checked_with_fee(
+                self.token_program.key,
+                self.source.key,
+                self.mint.key,
+                self.destination.key,
+                authority.key,
+                &[],
+                settlement.debit,
+                decimals,
+                settlement.fee,
+            )?
+        } else {
+            spl_token_2022::instruction::transfer_checked(
+                self.token_program.key,
+                self.source.key,
+                self.mint.key,
+                self.destination.key,
+                authority.key,
+                &[],
+                settlement.debit,
+                decimals,
+            )?
+        };
+        invoke_signed(
+            &ix,
+            &[
+                self.source.clone(),
+                self.mint.clone(),
+                self.destination.clone(),
+                authority.clone(),
+                self.token_program.clone(),
+            ],
+            &[authority_seeds],
+        )
+    }
+}
+
+fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if *token_program.key != spl_token::ID && *token_program.key != spl_token_2022::ID {
+        msg!(" Token program mismatch");
+        return Err(TossError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// Check that `account` is a `token_program` account holding `mint` and
+/// owned by `owner`, returning its balance
+pub(crate) fn check_token_account(
+    account: &AccountInfo,
+    token_program_id: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Result<u64, ProgramError> {
+    if account.owner != token_program_id {
+        msg!(
+            " Token account {} not owned by the token program",
+            account.key
+        );
+        return Err(TossError::InvalidTokenAccount.into());
+    }
+    let data = account.data.borrow();
+    let token_account = StateWithExtensions::<Account>::unpack(&data)
+        .map_err(|_| TossError::InvalidTokenAccount)?
+        .base;
+    if token_account.mint != *mint {
+        msg!(" Token account {} holds another mint", account.key);
+        return Err(TossError::InvalidTokenAccount.into());
+    }
+    if token_account.owner != *owner {
+        msg!(" Token account {} not owned by intent party", account.key);
+        return Err(TossError::TokenAccountOwnerMismatch.into());
+    }
+    Ok(token_account.amount)
+}
+
+/// Whether `account` is a Token-2022 account that rejects transfers without
+/// a preceding memo
+fn requires_incoming_memo(account: &AccountInfo) -> bool {
+    if *account.owner != spl_token_2022::ID {
+        return false;
+    }
+    let data = account.data.borrow();
+    StateWithExtensions::<Account>::unpack(&data)
+        .ok()
+        .and_then(|state| state.get_extension::<MemoTransfer>().ok().copied())
+        .is_some_and(|memo_transfer| memo_transfer.require_incoming_transfer_memos.into())
+}
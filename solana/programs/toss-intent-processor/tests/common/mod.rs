@@ -0,0 +1,462 @@
+#![allow(dead_code)]
+
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+use spl_token_2022::{
+    extension::{
+        memo_transfer::MemoTransfer,
+        transfer_fee::{TransferFee, TransferFeeAmount, TransferFeeConfig},
+        ExtensionType, StateWithExtensionsMut,
+    },
+    state as token_2022,
+};
+use toss_intent_processor::{
+    ed25519,
+    error::TossError,
+    intent::IntentEnvelope,
+    intent_hash, process_instruction,
+    signing::{signing_preimage, CLUSTER},
+    state::{NonceTracker, SettlementReceipt, Vault},
+    SolanaIntent, TossIntentInstruction,
+};
+
+pub const SENDER_LAMPORTS: u64 = 10_000_000_000;
+
+pub fn program_test(program_id: Pubkey) -> ProgramTest {
+    ProgramTest::new(
+        "toss_intent_processor",
+        program_id,
+        processor!(process_instruction),
+    )
+}
+
+/// Fund `keypair` as a system account inside the test bank
+pub fn fund(program_test: &mut ProgramTest, keypair: &Keypair, lamports: u64) {
+    program_test.add_account(
+        keypair.pubkey(),
+        Account {
+            lamports,
+            owner: system_program::ID,
+            ..Account::default()
+        },
+    );
+}
+
+fn add_packed<T: Pack>(program_test: &mut ProgramTest, address: Pubkey, state: T) {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    program_test.add_account(
+        address,
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: spl_token::ID,
+            ..Account::default()
+        },
+    );
+}
+
+/// Add an initialized SPL Token mint
+pub fn add_mint(program_test: &mut ProgramTest, mint: Pubkey, decimals: u8) {
+    add_packed(
+        program_test,
+        mint,
+        Mint {
+            mint_authority: COption::Some(Pubkey::new_unique()),
+            supply: 100_000_000,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+    );
+}
+
+/// Add an initialized SPL Token account
+pub fn add_token_account(
+    program_test: &mut ProgramTest,
+    address: Pubkey,
+    mint: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+) {
+    add_packed(
+        program_test,
+        address,
+        TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+    );
+}
+
+/// Add an initialized Token-2022 mint, charging `transfer_fee` on every
+/// transfer if given
+pub fn add_token_2022_mint(
+    program_test: &mut ProgramTest,
+    mint: Pubkey,
+    decimals: u8,
+    transfer_fee: Option<TransferFee>,
+) {
+    let extensions: &[ExtensionType] = if transfer_fee.is_some() {
+        &[ExtensionType::TransferFeeConfig]
+    } else {
+        &[]
+    };
+    let len = ExtensionType::try_calculate_account_len::<token_2022::Mint>(extensions).unwrap();
+    let mut data = vec![0; len];
+    let mut state =
+        StateWithExtensionsMut::<token_2022::Mint>::unpack_uninitialized(&mut data).unwrap();
+    if let Some(transfer_fee) = transfer_fee {
+        let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = transfer_fee;
+        config.newer_transfer_fee = transfer_fee;
+    }
+    state.base = token_2022::Mint {
+        mint_authority: COption::Some(Pubkey::new_unique()),
+        supply: 100_000_000,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    state.pack_base();
+    if transfer_fee.is_some() {
+        state.init_account_type().unwrap();
+    }
+    add_token_2022_data(program_test, mint, data);
+}
+
+/// Add an initialized Token-2022 account, optionally requiring incoming
+/// transfers to carry a memo
+pub fn add_token_2022_account(
+    program_test: &mut ProgramTest,
+    address: Pubkey,
+    mint: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+    require_memo: bool,
+) {
+    let mut extensions = vec![ExtensionType::TransferFeeAmount];
+    if require_memo {
+        extensions.push(ExtensionType::MemoTransfer);
+    }
+    let len = ExtensionType::try_calculate_account_len::<token_2022::Account>(&extensions).unwrap();
+    let mut data = vec![0; len];
+    let mut state =
+        StateWithExtensionsMut::<token_2022::Account>::unpack_uninitialized(&mut data).unwrap();
+    state.init_extension::<TransferFeeAmount>(true).unwrap();
+    if require_memo {
+        state
+            .init_extension::<MemoTransfer>(true)
+            .unwrap()
+            .require_incoming_transfer_memos = true.into();
+    }
+    state.base = token_2022::Account {
+        mint,
+        owner,
+        amount,
+        state: token_2022::AccountState::Initialized,
+        ..token_2022::Account::default()
+    };
+    state.pack_base();
+    state.init_account_type().unwrap();
+    add_token_2022_data(program_test, address, data);
+}
+
+fn add_token_2022_data(program_test: &mut ProgramTest, address: Pubkey, data: Vec<u8>) {
+    program_test.add_account(
+        address,
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: spl_token_2022::ID,
+            ..Account::default()
+        },
+    );
+}
+
+/// Add an initialized vault for `owner` holding `lamports`
+pub fn add_vault(
+    program_test: &mut ProgramTest,
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    lamports: u64,
+) -> Pubkey {
+    let (address, bump) = Vault::find_address(owner, program_id);
+    program_test.add_account(
+        address,
+        Account {
+            lamports,
+            data: borsh::to_vec(&Vault::new(*owner, bump)).unwrap(),
+            owner: *program_id,
+            ..Account::default()
+        },
+    );
+    address
+}
+
+pub fn intent(from: Pubkey, to: Pubkey, amount: u64) -> SolanaIntent {
+    SolanaIntent {
+        from,
+        to,
+        amount,
+        nonce: 1,
+        expiry: i64::MAX as u64,
+        nonce_account: None,
+        nonce_auth: None,
+    }
+}
+
+pub fn sign(keypair: &Keypair, message: &[u8]) -> [u8; 64] {
+    keypair.sign_message(message).as_ref().try_into().unwrap()
+}
+
+/// Encode `intent` as a v1 `intent_data` payload
+pub fn pack(intent: &SolanaIntent) -> Vec<u8> {
+    IntentEnvelope::V1(intent.clone()).pack()
+}
+
+/// Signing preimage of `intent_data` for `program_id` on the test cluster
+pub fn preimage(program_id: &Pubkey, intent_data: &[u8]) -> Vec<u8> {
+    signing_preimage(program_id, CLUSTER, intent_data)
+}
+
+pub fn process_intent_ix(
+    program_id: Pubkey,
+    intent: &SolanaIntent,
+    signature: [u8; 64],
+    intent_data: Vec<u8>,
+    relayer: Pubkey,
+) -> Instruction {
+    let receipt = SettlementReceipt::find_address(&intent_hash(&intent_data), &program_id).0;
+    let data = borsh::to_vec(&TossIntentInstruction::ProcessIntent {
+        signature,
+        intent_data,
+    })
+    .unwrap();
+
+    let mut accounts = vec![
+        AccountMeta::new(intent.from, false),
+        AccountMeta::new(intent.to, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        AccountMeta::new(
+            NonceTracker::find_address(&intent.from, &program_id).0,
+            false,
+        ),
+        AccountMeta::new(receipt, false),
+        AccountMeta::new(relayer, true),
+        AccountMeta::new(Vault::find_address(&intent.from, &program_id).0, false),
+    ];
+    if let (Some(nonce_account), Some(nonce_auth)) = (intent.nonce_account, intent.nonce_auth) {
+        accounts.push(AccountMeta::new(nonce_account, false));
+        accounts.push(AccountMeta::new_readonly(nonce_auth, true));
+        #[allow(deprecated)]
+        accounts.push(AccountMeta::new_readonly(
+            sysvar::recent_blockhashes::ID,
+            false,
+        ));
+    }
+
+    Instruction {
+        program_id,
+        accounts,
+        data,
+    }
+}
+
+/// `ProcessIntentCompact` settling `intent` with the same accounts as
+/// `process_intent_ix`
+pub fn process_intent_compact_ix(
+    program_id: Pubkey,
+    intent: &SolanaIntent,
+    signature: [u8; 64],
+    intent_data: Vec<u8>,
+    relayer: Pubkey,
+) -> Instruction {
+    let preimage = preimage(&program_id, &intent_data);
+    let mut ix = process_intent_ix(program_id, intent, signature, intent_data, relayer);
+    ix.data = borsh::to_vec(&TossIntentInstruction::ProcessIntentCompact {
+        signature,
+        preimage,
+    })
+    .unwrap();
+    ix
+}
+
+/// Assert that instruction `instruction_index` failed with `expected`
+pub fn assert_toss_error(
+    result: Result<(), BanksClientError>,
+    instruction_index: u8,
+    expected: TossError,
+) -> TossError {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(index, InstructionError::Custom(code)) => {
+            assert_eq!(index, instruction_index);
+            assert_eq!(TossError::from_code(code), Some(expected));
+            expected
+        }
+        other => panic!("expected {expected:?}, got {other:?}"),
+    }
+}
+
+/// A started test bank with a funded intent sender and sender vault
+pub struct IntentTest {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub sender: Keypair,
+    pub vault: Pubkey,
+}
+
+impl IntentTest {
+    pub async fn start() -> Self {
+        Self::start_with(|_, _| {}).await
+    }
+
+    /// Start the bank after `configure` has added any extra accounts for
+    /// the sender, given the sender's vault address
+    pub async fn start_with(configure: impl FnOnce(&mut ProgramTest, &Pubkey)) -> Self {
+        let program_id = Pubkey::new_unique();
+        let sender = Keypair::new();
+        let mut program_test = program_test(program_id);
+        fund(&mut program_test, &sender, SENDER_LAMPORTS);
+        let vault = add_vault(
+            &mut program_test,
+            &program_id,
+            &sender.pubkey(),
+            SENDER_LAMPORTS,
+        );
+        configure(&mut program_test, &vault);
+        Self {
+            context: program_test.start_with_context().await,
+            program_id,
+            sender,
+            vault,
+        }
+    }
+
+    /// Sign `intent` as the sender and submit it for settlement
+    pub async fn settle(&mut self, intent: &SolanaIntent) -> Result<(), BanksClientError> {
+        self.settle_envelope(&IntentEnvelope::V1(intent.clone()), &[])
+            .await
+    }
+
+    /// Sign `envelope` as the sender and submit it, adding `signers` to the
+    /// settlement transaction
+    pub async fn settle_envelope(
+        &mut self,
+        envelope: &IntentEnvelope,
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        self.settle_with_accounts(envelope, &[], signers).await
+    }
+
+    /// Like `settle_envelope`, passing `accounts` to `ProcessIntent` right
+    /// after the sender vault
+    pub async fn settle_with_accounts(
+        &mut self,
+        envelope: &IntentEnvelope,
+        accounts: &[AccountMeta],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        let mut instructions = self.settlement_instructions(envelope);
+        instructions[1]
+            .accounts
+            .splice(8..8, accounts.iter().cloned());
+        self.process(&instructions, signers).await
+    }
+
+    /// Ed25519 and `ProcessIntent` instructions settling `envelope` signed by
+    /// the sender, with the payer as relayer
+    pub fn settlement_instructions(&self, envelope: &IntentEnvelope) -> [Instruction; 2] {
+        let intent = envelope.intent();
+        let intent_data = envelope.pack();
+        let message = preimage(&self.program_id, &intent_data);
+        let signature = sign(&self.sender, &message);
+        [
+            ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+            process_intent_ix(
+                self.program_id,
+                intent,
+                signature,
+                intent_data,
+                self.context.payer.pubkey(),
+            ),
+        ]
+    }
+
+    /// Submit `instructions` signed by the payer, `signers` and the sender
+    /// if any instruction needs it
+    pub async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+        self.process_with_blockhash(instructions, signers, blockhash)
+            .await
+    }
+
+    /// Submit `instructions` with an explicit recent blockhash, such as a
+    /// durable nonce
+    pub async fn process_with_blockhash(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+        blockhash: Hash,
+    ) -> Result<(), BanksClientError> {
+        let mut all_signers = vec![&self.context.payer];
+        let sender = self.sender.pubkey();
+        if instructions
+            .iter()
+            .flat_map(|ix| &ix.accounts)
+            .any(|meta| meta.pubkey == sender && meta.is_signer)
+        {
+            all_signers.push(&self.sender);
+        }
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        // Durable nonces are not recent blockhashes, so submit through the
+        // path that skips the client-side blockhash expiry lookup
+        self.context
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await?
+            .result
+            .map_err(BanksClientError::TransactionError)
+    }
+
+    /// Move the bank's unix time forward by `seconds`
+    pub async fn advance_clock(&mut self, seconds: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += seconds;
+        self.context.set_sysvar(&clock);
+    }
+
+    pub async fn balance(&mut self, address: Pubkey) -> u64 {
+        self.context
+            .banks_client
+            .get_balance(address)
+            .await
+            .unwrap()
+    }
+}
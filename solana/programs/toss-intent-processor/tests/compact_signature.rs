@@ -0,0 +1,299 @@
+mod common;
+
+use common::*;
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+use toss_intent_processor::{
+    compact_signature_offsets, ed25519,
+    error::TossError,
+    signing::{signing_preimage, CLUSTER},
+    BatchMode, SolanaIntent, TossIntentInstruction,
+};
+
+/// Referencing Ed25519Program instruction at index `at` and the
+/// `ProcessIntentCompact` instruction at `at + 1` it verifies
+fn compact_instructions(
+    test: &IntentTest,
+    intent: &SolanaIntent,
+    signer: &Keypair,
+    at: u16,
+) -> [Instruction; 2] {
+    let intent_data = pack(intent);
+    let message = preimage(&test.program_id, &intent_data);
+    let signature = sign(signer, &message);
+    [
+        ed25519::new_ed25519_referencing_instruction(&compact_signature_offsets(
+            at + 1,
+            message.len(),
+        )),
+        process_intent_compact_ix(
+            test.program_id,
+            intent,
+            signature,
+            intent_data,
+            test.context.payer.pubkey(),
+        ),
+    ]
+}
+
+#[tokio::test]
+async fn test_compact_intent_settles() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let instructions = compact_instructions(&test, &intent, &test.sender, 0);
+    assert_eq!(instructions[0].data.len(), ed25519::DATA_START);
+
+    test.process(&instructions, &[]).await.unwrap();
+
+    assert_eq!(test.balance(intent.to).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_offsets_into_other_instruction_are_rejected() {
+    let mut test = IntentTest::start().await;
+    let first = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut second = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    second.nonce = 2;
+    let [_, first_ix] = compact_instructions(&test, &first, &test.sender, 0);
+    let [_, second_ix] = compact_instructions(&test, &second, &test.sender, 0);
+    // A valid signature check, but of the instruction after the one it
+    // precedes
+    let offsets = compact_signature_offsets(2, preimage(&test.program_id, &pack(&second)).len());
+    let instructions = [
+        ed25519::new_ed25519_referencing_instruction(&offsets),
+        first_ix,
+        second_ix,
+    ];
+
+    let result = test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::MalformedSignatureInstruction);
+}
+
+#[tokio::test]
+async fn test_compact_preimage_for_other_program_is_rejected() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let [_, mut ix] = compact_instructions(&test, &intent, &test.sender, 0);
+    let message = signing_preimage(&Pubkey::new_unique(), CLUSTER, &pack(&intent));
+    let signature = sign(&test.sender, &message);
+    ix.data = borsh::to_vec(&TossIntentInstruction::ProcessIntentCompact {
+        signature,
+        preimage: message.clone(),
+    })
+    .unwrap();
+    let instructions = [
+        ed25519::new_ed25519_referencing_instruction(&compact_signature_offsets(1, message.len())),
+        ix,
+    ];
+
+    let result = test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::MessageMismatch);
+}
+
+/// Ways to lay out settlements of plain lamport intents in a transaction
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    /// Self-contained Ed25519Program instruction before each `ProcessIntent`
+    Separate,
+    /// Referencing Ed25519Program instruction before each
+    /// `ProcessIntentCompact`
+    Compact,
+    /// `ProcessIntentBatch` with `new_ed25519_batch_instruction`
+    Batch,
+    /// `ProcessIntentBatch` with `new_ed25519_intent_batch_instruction`
+    CompactBatch,
+}
+
+/// Serialized size of a transaction settling `count` intents, from distinct
+/// senders or all from one, either legacy or v0 with every account the
+/// runtime allows in an address lookup table
+fn transaction_size(
+    layout: Layout,
+    count: usize,
+    distinct_senders: bool,
+    lookup_table: bool,
+) -> usize {
+    let program_id = Pubkey::new_unique();
+    let relayer = Pubkey::new_unique();
+    let one_sender = Keypair::new();
+    let signed: Vec<_> = (0..count)
+        .map(|i| {
+            let keypair = if distinct_senders {
+                Keypair::new()
+            } else {
+                one_sender.insecure_clone()
+            };
+            let mut intent = intent(keypair.pubkey(), Pubkey::new_unique(), 1_000_000);
+            intent.nonce = i as u64 + 1;
+            let intent_data = pack(&intent);
+            let message = preimage(&program_id, &intent_data);
+            let signature = sign(&keypair, &message);
+            (intent, intent_data, message, signature)
+        })
+        .collect();
+
+    let mut instructions = Vec::new();
+    match layout {
+        Layout::Separate | Layout::Compact => {
+            for (intent, intent_data, message, signature) in &signed {
+                if let Layout::Separate = layout {
+                    instructions.push(ed25519::new_ed25519_instruction(
+                        &intent.from,
+                        signature,
+                        message,
+                    ));
+                    instructions.push(process_intent_ix(
+                        program_id,
+                        intent,
+                        *signature,
+                        intent_data.clone(),
+                        relayer,
+                    ));
+                } else {
+                    let offsets =
+                        compact_signature_offsets(instructions.len() as u16 + 1, message.len());
+                    instructions.push(ed25519::new_ed25519_referencing_instruction(&offsets));
+                    instructions.push(process_intent_compact_ix(
+                        program_id,
+                        intent,
+                        *signature,
+                        intent_data.clone(),
+                        relayer,
+                    ));
+                }
+            }
+        }
+        Layout::Batch | Layout::CompactBatch => {
+            instructions.push(if let Layout::Batch = layout {
+                let signatures: Vec<_> = signed
+                    .iter()
+                    .map(|(intent, _, message, signature)| {
+                        (&intent.from, signature, message.as_slice())
+                    })
+                    .collect();
+                ed25519::new_ed25519_batch_instruction(&signatures)
+            } else {
+                let signatures: Vec<_> = signed
+                    .iter()
+                    .map(|(_, _, message, signature)| (signature, message.as_slice()))
+                    .collect();
+                ed25519::new_ed25519_intent_batch_instruction(&signatures)
+            });
+            // The batch shares the relayer, system program and instructions
+            // sysvar, then takes each intent's `ProcessIntent` accounts
+            // except its sender
+            let mut batch = process_intent_ix(
+                program_id,
+                &signed[0].0,
+                signed[0].3,
+                signed[0].1.clone(),
+                relayer,
+            );
+            batch.accounts = vec![
+                batch.accounts[6].clone(),
+                batch.accounts[2].clone(),
+                batch.accounts[3].clone(),
+            ];
+            for (intent, intent_data, _, signature) in &signed {
+                let ix =
+                    process_intent_ix(program_id, intent, *signature, intent_data.clone(), relayer);
+                let [_, recipient, _, _, tracker, receipt, _, vault] = &ix.accounts[..] else {
+                    unreachable!()
+                };
+                batch
+                    .accounts
+                    .extend([recipient, tracker, receipt, vault].map(Clone::clone));
+            }
+            batch.data = borsh::to_vec(&TossIntentInstruction::ProcessIntentBatch {
+                mode: BatchMode::SkipFailed,
+            })
+            .unwrap();
+            instructions.push(batch);
+        }
+    }
+
+    if !lookup_table {
+        let transaction = Transaction::new_with_payer(&instructions, Some(&relayer));
+        return bincode::serialize(&transaction).unwrap().len();
+    }
+    let table = AddressLookupTableAccount {
+        key: Pubkey::new_unique(),
+        addresses: instructions
+            .iter()
+            .flat_map(|ix| ix.accounts.iter().map(|meta| meta.pubkey))
+            .filter(|address| *address != relayer)
+            .collect(),
+    };
+    let message =
+        v0::Message::try_compile(&relayer, &instructions, &[table], Hash::default()).unwrap();
+    let signatures = vec![Signature::default(); message.header.num_required_signatures as usize];
+    let transaction = VersionedTransaction {
+        signatures,
+        message: VersionedMessage::V0(message),
+    };
+    bincode::serialize(&transaction).unwrap().len()
+}
+
+/// Most intents that fit in one transaction
+fn intents_per_transaction(layout: Layout, distinct_senders: bool, lookup_table: bool) -> usize {
+    (1..)
+        .find(|&count| {
+            transaction_size(layout, count + 1, distinct_senders, lookup_table) > PACKET_DATA_SIZE
+        })
+        .unwrap()
+}
+
+/// Bytes each further intent from a new sender adds to a legacy transaction
+fn bytes_per_intent(layout: Layout) -> usize {
+    transaction_size(layout, 2, true, false) - transaction_size(layout, 1, true, false)
+}
+
+#[test]
+fn test_bytes_saved_per_intent() {
+    // Referencing `ProcessIntentCompact` drops the duplicated signature,
+    // public key and message from each Ed25519Program instruction, cutting
+    // 188 of the 584 bytes a separately verified intent costs. A batch
+    // already shares its accounts and instructions, so reading keys from
+    // the preimages saves just the 32-byte key
+    let measured = [
+        Layout::Separate,
+        Layout::Compact,
+        Layout::Batch,
+        Layout::CompactBatch,
+    ]
+    .map(bytes_per_intent);
+
+    assert_eq!(measured, [584, 396, 378, 346]);
+}
+
+#[test]
+fn test_intents_per_transaction() {
+    let measured: Vec<_> = [
+        Layout::Separate,
+        Layout::Compact,
+        Layout::Batch,
+        Layout::CompactBatch,
+    ]
+    .into_iter()
+    .map(|layout| {
+        [(true, false), (false, false), (true, true), (false, true)]
+            .map(|(distinct, lookup_table)| intents_per_transaction(layout, distinct, lookup_table))
+    })
+    .collect();
+
+    // [distinct senders, one sender] for legacy, then v0 with a lookup table
+    assert_eq!(
+        measured,
+        vec![[1, 1, 2, 2], [2, 2, 4, 4], [2, 2, 3, 3], [2, 3, 4, 4]]
+    );
+}
@@ -0,0 +1,406 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+};
+use toss_intent_processor::{
+    ed25519,
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2, SplitLeg, TokenTransfer},
+    intent_hash,
+    state::{FraudRecord, SettlementReceipt, Vault, BOND_RELEASE_DELAY, DEFAULT_WITHDRAWAL_DELAY},
+    SolanaIntent, TossIntentInstruction,
+};
+
+const BOND: u64 = 2_000_000_000;
+
+/// A bank whose sender has posted `BOND` and settled an intent with
+/// nonce 1
+struct DoubleSpendTest {
+    test: IntentTest,
+    settled: SolanaIntent,
+}
+
+impl DoubleSpendTest {
+    async fn start() -> Self {
+        let mut test = IntentTest::start().await;
+        let sender = test.sender.pubkey();
+        let post_bond = Instruction {
+            program_id: test.program_id,
+            accounts: vec![
+                AccountMeta::new(sender, true),
+                AccountMeta::new(test.vault, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: borsh::to_vec(&TossIntentInstruction::PostBond { amount: BOND }).unwrap(),
+        };
+        test.process(&[post_bond], &[]).await.unwrap();
+
+        let settled = intent(sender, Pubkey::new_unique(), 1_000_000);
+        test.settle(&settled).await.unwrap();
+        Self { test, settled }
+    }
+
+    /// A second intent reusing the settled intent's nonce
+    fn conflicting(&self) -> SolanaIntent {
+        intent(self.settled.from, Pubkey::new_unique(), 3_000_000)
+    }
+
+    fn report_instructions(
+        &self,
+        settled: &SolanaIntent,
+        conflicting: &SolanaIntent,
+        victim: Pubkey,
+    ) -> Vec<Instruction> {
+        self.report_envelope_instructions(settled, &IntentEnvelope::V1(conflicting.clone()), victim)
+    }
+
+    fn report_envelope_instructions(
+        &self,
+        settled: &SolanaIntent,
+        conflicting: &IntentEnvelope,
+        victim: Pubkey,
+    ) -> Vec<Instruction> {
+        let program_id = self.test.program_id;
+        let signed = |envelope: &IntentEnvelope| {
+            let data = envelope.pack();
+            let message = preimage(&program_id, &data);
+            let signature = sign(&self.test.sender, &message);
+            let ix =
+                ed25519::new_ed25519_instruction(&envelope.intent().from, &signature, &message);
+            (signature, data, ix)
+        };
+        let (settled_signature, settled_intent_data, settled_ix) =
+            signed(&IntentEnvelope::V1(settled.clone()));
+        let (conflicting_signature, conflicting_intent_data, conflicting_ix) = signed(conflicting);
+        let receipt =
+            SettlementReceipt::find_address(&intent_hash(&settled_intent_data), &program_id).0;
+        let fraud_record =
+            FraudRecord::find_address(&intent_hash(&conflicting_intent_data), &victim, &program_id)
+                .0;
+        let report = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(self.test.context.payer.pubkey(), true),
+                AccountMeta::new(self.test.vault, false),
+                AccountMeta::new_readonly(receipt, false),
+                AccountMeta::new(victim, false),
+                AccountMeta::new(fraud_record, false),
+                AccountMeta::new_readonly(sysvar::instructions::ID, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: borsh::to_vec(&TossIntentInstruction::ReportDoubleSpend {
+                settled_signature,
+                settled_intent_data,
+                conflicting_signature,
+                conflicting_intent_data,
+            })
+            .unwrap(),
+        };
+        vec![settled_ix, conflicting_ix, report]
+    }
+
+    /// Owner instruction on the sender's vault, with `extra` accounts after it
+    fn vault_ix(&self, instruction: TossIntentInstruction, extra: &[AccountMeta]) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.test.sender.pubkey(), true),
+            AccountMeta::new(self.test.vault, false),
+        ];
+        accounts.extend_from_slice(extra);
+        Instruction {
+            program_id: self.test.program_id,
+            accounts,
+            data: borsh::to_vec(&instruction).unwrap(),
+        }
+    }
+
+    async fn vault_state(&mut self) -> Vault {
+        let account = self
+            .test
+            .context
+            .banks_client
+            .get_account(self.test.vault)
+            .await
+            .unwrap()
+            .unwrap();
+        Vault::try_from_slice(&account.data).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_double_spend_slashes_bond_to_victim() {
+    let mut test = DoubleSpendTest::start().await;
+    let conflicting = test.conflicting();
+    let settled = test.settled.clone();
+
+    let result = test.test.settle(&conflicting).await;
+    assert_toss_error(result, 1, TossError::NonceAlreadyUsed);
+
+    let instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    test.test.process(&instructions, &[]).await.unwrap();
+
+    assert_eq!(test.test.balance(conflicting.to).await, conflicting.amount);
+    assert_eq!(test.vault_state().await.bond, BOND - conflicting.amount);
+    let address = FraudRecord::find_address(
+        &intent_hash(&pack(&conflicting)),
+        &conflicting.to,
+        &test.test.program_id,
+    )
+    .0;
+    let account = test
+        .test
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    let record = FraudRecord::try_from_slice(&account.data).unwrap();
+    assert_eq!(record.victim, conflicting.to);
+    assert_eq!(record.slashed, conflicting.amount);
+    assert_eq!(record.settled_intent_hash, intent_hash(&pack(&settled)));
+}
+
+#[tokio::test]
+async fn test_double_spend_is_reported_once() {
+    let mut test = DoubleSpendTest::start().await;
+    let conflicting = test.conflicting();
+    let settled = test.settled.clone();
+    let instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    test.test.process(&instructions, &[]).await.unwrap();
+
+    let instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 2, TossError::DoubleSpendAlreadyReported);
+    assert_eq!(test.test.balance(conflicting.to).await, conflicting.amount);
+}
+
+#[tokio::test]
+async fn test_each_conflicting_recipient_is_paid_from_remaining_bond() {
+    let mut test = DoubleSpendTest::start().await;
+    let settled = test.settled.clone();
+    let first = test.conflicting();
+    let instructions = test.report_instructions(&settled, &first, first.to);
+    test.test.process(&instructions, &[]).await.unwrap();
+
+    let mut second = test.conflicting();
+    second.amount = BOND;
+    let instructions = test.report_instructions(&settled, &second, second.to);
+    test.test.process(&instructions, &[]).await.unwrap();
+
+    assert_eq!(test.test.balance(first.to).await, first.amount);
+    assert_eq!(test.test.balance(second.to).await, BOND - first.amount);
+    assert_eq!(test.vault_state().await.bond, 0);
+}
+
+#[tokio::test]
+async fn test_victim_reports_and_funds_record() {
+    let mut test = DoubleSpendTest::start().await;
+    let victim = Keypair::new();
+    let payer = test.test.context.payer.pubkey();
+    let fund = system_instruction::transfer(&payer, &victim.pubkey(), 100_000_000);
+    test.test.process(&[fund], &[]).await.unwrap();
+    let settled = test.settled.clone();
+    let conflicting = intent(settled.from, victim.pubkey(), 3_000_000);
+    let mut instructions = test.report_instructions(&settled, &conflicting, victim.pubkey());
+    instructions[2].accounts[0] = AccountMeta::new(victim.pubkey(), true);
+
+    test.test.process(&instructions, &[&victim]).await.unwrap();
+
+    let rent = test
+        .test
+        .context
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(FraudRecord::LEN);
+    assert_eq!(
+        test.test.balance(victim.pubkey()).await,
+        100_000_000 - rent + conflicting.amount
+    );
+    assert_eq!(test.vault_state().await.bond, BOND - conflicting.amount);
+}
+
+#[tokio::test]
+async fn test_split_leg_recipients_are_paid_their_leg() {
+    let mut test = DoubleSpendTest::start().await;
+    let settled = test.settled.clone();
+    let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let conflicting = IntentEnvelope::V2(SolanaIntentV2 {
+        intent: test.conflicting(),
+        extensions: vec![IntentExtension::Split(vec![
+            SplitLeg {
+                recipient: first,
+                amount: 5_000_000,
+            },
+            SplitLeg {
+                recipient: second,
+                amount: 7_000_000,
+            },
+        ])],
+    });
+
+    for victim in [conflicting.intent().to, first, second] {
+        let instructions = test.report_envelope_instructions(&settled, &conflicting, victim);
+        test.test.process(&instructions, &[]).await.unwrap();
+    }
+    let instructions = test.report_envelope_instructions(&settled, &conflicting, first);
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 2, TossError::DoubleSpendAlreadyReported);
+    assert_eq!(
+        test.test.balance(conflicting.intent().to).await,
+        conflicting.intent().amount
+    );
+    assert_eq!(test.test.balance(first).await, 5_000_000);
+    assert_eq!(test.test.balance(second).await, 7_000_000);
+    assert_eq!(
+        test.vault_state().await.bond,
+        BOND - conflicting.total_amount().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_token_double_spend_is_not_slashed() {
+    let mut test = DoubleSpendTest::start().await;
+    let settled = test.settled.clone();
+    let conflicting = IntentEnvelope::V2(SolanaIntentV2 {
+        intent: test.conflicting(),
+        extensions: vec![IntentExtension::Token(TokenTransfer {
+            mint: Pubkey::new_unique(),
+            decimals: 6,
+        })],
+    });
+
+    let victim = conflicting.intent().to;
+    let instructions = test.report_envelope_instructions(&settled, &conflicting, victim);
+    let result = test.test.process(&instructions, &[]).await;
+
+    let error = assert_toss_error(result, 2, TossError::TokenIntentNotSlashable);
+    assert!(!error.is_retryable());
+    assert_eq!(test.vault_state().await.bond, BOND);
+}
+
+#[tokio::test]
+async fn test_report_during_bond_release_still_slashes() {
+    let mut test = DoubleSpendTest::start().await;
+    let destination = Pubkey::new_unique();
+    let close = test.vault_ix(
+        TossIntentInstruction::CloseVault,
+        &[AccountMeta::new(destination, false)],
+    );
+    let request_withdrawal = test.vault_ix(
+        TossIntentInstruction::RequestWithdrawal {
+            mint: system_program::ID,
+            amount: 1,
+        },
+        &[],
+    );
+    test.test.process(&[request_withdrawal], &[]).await.unwrap();
+    test.test
+        .advance_clock(DEFAULT_WITHDRAWAL_DELAY as i64)
+        .await;
+
+    let result = test.test.process(std::slice::from_ref(&close), &[]).await;
+    assert_toss_error(result, 0, TossError::BondReleaseNotRequested);
+
+    let release = test.vault_ix(TossIntentInstruction::RequestBondRelease, &[]);
+    test.test.process(&[release], &[]).await.unwrap();
+    test.test.advance_clock(BOND_RELEASE_DELAY as i64 - 1).await;
+    let result = test.test.process(std::slice::from_ref(&close), &[]).await;
+    let error = assert_toss_error(result, 0, TossError::BondLocked);
+    assert!(error.is_retryable());
+
+    let conflicting = test.conflicting();
+    let settled = test.settled.clone();
+    let instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    test.test.process(&instructions, &[]).await.unwrap();
+    assert_eq!(test.test.balance(conflicting.to).await, conflicting.amount);
+    assert_eq!(test.vault_state().await.bond, BOND - conflicting.amount);
+
+    test.test.advance_clock(1).await;
+    let vault_balance = test.test.balance(test.test.vault).await;
+    test.test.process(&[close], &[]).await.unwrap();
+    assert_eq!(test.test.balance(destination).await, vault_balance);
+}
+
+#[tokio::test]
+async fn test_intents_with_different_nonces_do_not_conflict() {
+    let mut test = DoubleSpendTest::start().await;
+    let mut conflicting = test.conflicting();
+    conflicting.nonce = 2;
+    let settled = test.settled.clone();
+
+    let instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 2, TossError::IntentsDoNotConflict);
+}
+
+#[tokio::test]
+async fn test_reported_intent_must_have_settled() {
+    let mut test = DoubleSpendTest::start().await;
+    let conflicting = test.conflicting();
+    let mut unsettled = test.conflicting();
+    unsettled.amount = 4_000_000;
+
+    let instructions = test.report_instructions(&unsettled, &conflicting, conflicting.to);
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 2, TossError::IntentNotSettled);
+}
+
+#[tokio::test]
+async fn test_bond_goes_to_conflicting_recipient_only() {
+    let mut test = DoubleSpendTest::start().await;
+    let conflicting = test.conflicting();
+    let settled = test.settled.clone();
+
+    let instructions = test.report_instructions(&settled, &conflicting, Pubkey::new_unique());
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 2, TossError::VictimMismatch);
+}
+
+#[tokio::test]
+async fn test_both_intents_must_be_signed_by_sender() {
+    let mut test = DoubleSpendTest::start().await;
+    let conflicting = test.conflicting();
+    let settled = test.settled.clone();
+    let mut instructions = test.report_instructions(&settled, &conflicting, conflicting.to);
+    instructions.remove(0);
+
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::MissingSignatureInstruction);
+}
+
+#[tokio::test]
+async fn test_bond_is_not_spendable() {
+    let mut test = DoubleSpendTest::start().await;
+    let rent = test
+        .test
+        .context
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(Vault::LEN);
+    let spendable = test.test.balance(test.test.vault).await - rent - BOND;
+    let mut intent = intent(test.settled.from, Pubkey::new_unique(), spendable + 1);
+    intent.nonce = 2;
+
+    let result = test
+        .test
+        .settle_envelope(&IntentEnvelope::V1(intent), &[])
+        .await;
+
+    assert_toss_error(result, 1, TossError::InsufficientFunds);
+}
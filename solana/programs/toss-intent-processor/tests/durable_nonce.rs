@@ -0,0 +1,197 @@
+mod common;
+
+use common::*;
+use solana_program_test::ProgramTest;
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    nonce::state::{Data, DurableNonce, State, Versions},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2},
+};
+
+const NONCE_LAMPORTS: u64 = 10_000_000;
+
+fn add_nonce_account(program_test: &mut ProgramTest, address: Pubkey, state: State) {
+    // Nonce accounts are allocated at full size even while uninitialized
+    let mut data = bincode::serialize(&Versions::new(state)).unwrap();
+    data.resize(State::size(), 0);
+    program_test.add_account(
+        address,
+        Account {
+            lamports: NONCE_LAMPORTS,
+            data,
+            owner: system_program::ID,
+            ..Account::default()
+        },
+    );
+}
+
+fn initialized(authority: &Pubkey, durable_nonce: DurableNonce) -> State {
+    State::Initialized(Data::new(*authority, durable_nonce, 5000))
+}
+
+fn durable_intent(test: &IntentTest, nonce_account: Pubkey, nonce_auth: Pubkey) -> SolanaIntentV2 {
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce_account = Some(nonce_account);
+    intent.nonce_auth = Some(nonce_auth);
+    SolanaIntentV2 {
+        intent,
+        extensions: vec![],
+    }
+}
+
+async fn nonce_state(test: &mut IntentTest, address: Pubkey) -> Data {
+    let account = test
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    let versions: Versions = bincode::deserialize(&account.data).unwrap();
+    match versions.state() {
+        State::Initialized(data) => data.clone(),
+        State::Uninitialized => panic!("nonce account is uninitialized"),
+    }
+}
+
+#[tokio::test]
+async fn test_program_advances_committed_nonce() {
+    let nonce_account = Pubkey::new_unique();
+    let nonce_auth = Keypair::new();
+    let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+    let mut test = IntentTest::start_with(|program_test, _| {
+        add_nonce_account(
+            program_test,
+            nonce_account,
+            initialized(&nonce_auth.pubkey(), durable_nonce),
+        )
+    })
+    .await;
+
+    let mut intent = durable_intent(&test, nonce_account, nonce_auth.pubkey());
+    intent.extensions = vec![IntentExtension::DurableNonce(
+        durable_nonce.as_hash().to_bytes(),
+    )];
+    let recipient = intent.intent.to;
+    test.settle_envelope(&IntentEnvelope::V2(intent), &[&nonce_auth])
+        .await
+        .unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+    let state = nonce_state(&mut test, nonce_account).await;
+    assert_ne!(state.durable_nonce, durable_nonce);
+    assert_eq!(state.authority, nonce_auth.pubkey());
+}
+
+#[tokio::test]
+async fn test_durable_transaction_advances_nonce_once() {
+    let nonce_account = Pubkey::new_unique();
+    let nonce_auth = Keypair::new();
+    let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+    let mut test = IntentTest::start_with(|program_test, _| {
+        add_nonce_account(
+            program_test,
+            nonce_account,
+            initialized(&nonce_auth.pubkey(), durable_nonce),
+        )
+    })
+    .await;
+
+    let intent = durable_intent(&test, nonce_account, nonce_auth.pubkey());
+    let recipient = intent.intent.to;
+    let [ed25519_ix, process_ix] = test.settlement_instructions(&IntentEnvelope::V2(intent));
+    let instructions = [
+        system_instruction::advance_nonce_account(&nonce_account, &nonce_auth.pubkey()),
+        ed25519_ix,
+        process_ix,
+    ];
+    test.process_with_blockhash(&instructions, &[&nonce_auth], *durable_nonce.as_hash())
+        .await
+        .unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+    assert_ne!(
+        nonce_state(&mut test, nonce_account).await.durable_nonce,
+        durable_nonce
+    );
+}
+
+#[tokio::test]
+async fn test_intent_must_set_both_nonce_fields() {
+    let mut test = IntentTest::start().await;
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce_account = Some(Pubkey::new_unique());
+
+    let result = test.settle(&intent).await;
+
+    assert_toss_error(result, 1, TossError::IncompleteNonceConfig);
+}
+
+#[tokio::test]
+async fn test_uninitialized_nonce_account_is_rejected() {
+    let nonce_account = Pubkey::new_unique();
+    let nonce_auth = Keypair::new();
+    let mut test = IntentTest::start_with(|program_test, _| {
+        add_nonce_account(program_test, nonce_account, State::Uninitialized)
+    })
+    .await;
+
+    let intent = durable_intent(&test, nonce_account, nonce_auth.pubkey());
+    let result = test
+        .settle_envelope(&IntentEnvelope::V2(intent), &[&nonce_auth])
+        .await;
+
+    assert_toss_error(result, 1, TossError::NonceAccountUninitialized);
+}
+
+#[tokio::test]
+async fn test_nonce_account_authority_must_match_intent() {
+    let nonce_account = Pubkey::new_unique();
+    let nonce_auth = Keypair::new();
+    let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+    let mut test = IntentTest::start_with(|program_test, _| {
+        add_nonce_account(
+            program_test,
+            nonce_account,
+            initialized(&Pubkey::new_unique(), durable_nonce),
+        )
+    })
+    .await;
+
+    let intent = durable_intent(&test, nonce_account, nonce_auth.pubkey());
+    let result = test
+        .settle_envelope(&IntentEnvelope::V2(intent), &[&nonce_auth])
+        .await;
+
+    assert_toss_error(result, 1, TossError::NonceAccountAuthorityMismatch);
+}
+
+#[tokio::test]
+async fn test_committed_durable_nonce_must_match_account() {
+    let nonce_account = Pubkey::new_unique();
+    let nonce_auth = Keypair::new();
+    let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+    let mut test = IntentTest::start_with(|program_test, _| {
+        add_nonce_account(
+            program_test,
+            nonce_account,
+            initialized(&nonce_auth.pubkey(), durable_nonce),
+        )
+    })
+    .await;
+
+    let mut intent = durable_intent(&test, nonce_account, nonce_auth.pubkey());
+    intent.extensions = vec![IntentExtension::DurableNonce([1; 32])];
+    let result = test
+        .settle_envelope(&IntentEnvelope::V2(intent), &[&nonce_auth])
+        .await;
+
+    assert_toss_error(result, 1, TossError::DurableNonceMismatch);
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    clock::Clock,
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{HashLock, IntentEnvelope, IntentExtension, SolanaIntentV2},
+    intent_hash,
+    state::Escrow,
+    TossIntentInstruction,
+};
+
+const AMOUNT: u64 = 1_000_000;
+const SECRET: &[u8] = b"offline swap secret";
+/// Seconds from the test's start until the hash lock's deadline
+const LOCK_TIME: i64 = 3_600;
+
+/// A bank where the sender has locked `AMOUNT` for a new recipient
+struct HashLockTest {
+    test: IntentTest,
+    envelope: IntentEnvelope,
+    escrow: Pubkey,
+}
+
+impl HashLockTest {
+    async fn start() -> Self {
+        let mut test = IntentTest::start().await;
+        let clock: Clock = test.context.banks_client.get_sysvar().await.unwrap();
+        let envelope = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(test.sender.pubkey(), Pubkey::new_unique(), AMOUNT),
+            extensions: vec![IntentExtension::HashLock(HashLock {
+                hash: hash(SECRET).to_bytes(),
+                deadline: clock.unix_timestamp + LOCK_TIME,
+            })],
+        });
+        let escrow = Escrow::find_address(&intent_hash(&envelope.pack()), &test.program_id).0;
+        Self {
+            test,
+            envelope,
+            escrow,
+        }
+    }
+
+    async fn lock(&mut self) -> Result<(), BanksClientError> {
+        let escrow = self.escrow;
+        let envelope = self.envelope.clone();
+        self.test
+            .settle_with_accounts(&envelope, &[AccountMeta::new(escrow, false)], &[])
+            .await
+    }
+
+    fn release_ix(&self, instruction: TossIntentInstruction, destination: Pubkey) -> Instruction {
+        Instruction {
+            program_id: self.test.program_id,
+            accounts: vec![
+                AccountMeta::new(self.escrow, false),
+                AccountMeta::new(destination, false),
+                AccountMeta::new(self.test.context.payer.pubkey(), false),
+            ],
+            data: borsh::to_vec(&instruction).unwrap(),
+        }
+    }
+
+    async fn claim(&mut self, preimage: &[u8]) -> Result<(), BanksClientError> {
+        let claim = self.release_ix(
+            TossIntentInstruction::ClaimEscrow {
+                preimage: preimage.to_vec(),
+            },
+            self.envelope.intent().to,
+        );
+        self.test.process(&[claim], &[]).await
+    }
+
+    async fn refund(&mut self) -> Result<(), BanksClientError> {
+        let refund = self.release_ix(
+            TossIntentInstruction::RefundEscrow,
+            self.test.sender.pubkey(),
+        );
+        self.test.process(&[refund], &[]).await
+    }
+}
+
+#[tokio::test]
+async fn test_recipient_claims_with_preimage() {
+    let mut test = HashLockTest::start().await;
+    test.lock().await.unwrap();
+    let recipient = test.envelope.intent().to;
+    assert_eq!(test.test.balance(recipient).await, 0);
+    let escrowed = test.test.balance(test.escrow).await;
+    assert!(escrowed > AMOUNT);
+
+    let result = test.claim(b"wrong secret").await;
+    assert_toss_error(result, 0, TossError::PreimageMismatch);
+
+    test.claim(SECRET).await.unwrap();
+    assert_eq!(test.test.balance(recipient).await, AMOUNT);
+    assert_eq!(test.test.balance(test.escrow).await, 0);
+    let result = test.refund().await;
+    assert_toss_error(result, 0, TossError::InvalidEscrow);
+}
+
+#[tokio::test]
+async fn test_sender_is_refunded_after_deadline() {
+    let mut test = HashLockTest::start().await;
+    test.lock().await.unwrap();
+    let sender_before = test.test.balance(test.test.sender.pubkey()).await;
+
+    let result = test.refund().await;
+    assert_toss_error(result, 0, TossError::EscrowStillLocked);
+
+    test.test.advance_clock(LOCK_TIME + 1).await;
+    let result = test.claim(SECRET).await;
+    assert_toss_error(result, 0, TossError::HashLockExpired);
+
+    test.refund().await.unwrap();
+    assert_eq!(
+        test.test.balance(test.test.sender.pubkey()).await,
+        sender_before + AMOUNT
+    );
+    assert_eq!(test.test.balance(test.envelope.intent().to).await, 0);
+}
+
+#[tokio::test]
+async fn test_expired_hash_lock_cannot_settle() {
+    let mut test = HashLockTest::start().await;
+    test.test.advance_clock(LOCK_TIME + 1).await;
+
+    let result = test.lock().await;
+
+    assert_toss_error(result, 1, TossError::HashLockExpired);
+}
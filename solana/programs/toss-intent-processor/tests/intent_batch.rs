@@ -0,0 +1,468 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program, sysvar,
+    transaction::Transaction,
+};
+use spl_token::state::Account as TokenAccount;
+use toss_intent_processor::{
+    ed25519,
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, RelayerFee, SolanaIntentV2, TokenTransfer},
+    intent_hash,
+    state::{NonceTracker, SettlementReceipt, Vault},
+    BatchMode, SolanaIntent, TossIntentInstruction,
+};
+
+/// Ed25519 and `ProcessIntentBatch` instructions settling `intents`, each
+/// signed by its keypair, with `relayer` paying for receipts
+fn batch_instructions(
+    program_id: Pubkey,
+    intents: &[(&Keypair, &SolanaIntent)],
+    relayer: Pubkey,
+    mode: BatchMode,
+) -> [Instruction; 2] {
+    let envelopes: Vec<_> = intents
+        .iter()
+        .map(|(keypair, intent)| (*keypair, IntentEnvelope::V1((*intent).clone())))
+        .collect();
+    let intents: Vec<_> = envelopes
+        .iter()
+        .map(|(keypair, envelope)| (*keypair, envelope, &[][..]))
+        .collect();
+    batch_envelope_instructions(program_id, &intents, relayer, mode)
+}
+
+/// Like `batch_instructions`, for any envelope, passing each intent's
+/// accounts after its vault
+fn batch_envelope_instructions(
+    program_id: Pubkey,
+    intents: &[(&Keypair, &IntentEnvelope, &[AccountMeta])],
+    relayer: Pubkey,
+    mode: BatchMode,
+) -> [Instruction; 2] {
+    let signed: Vec<_> = intents
+        .iter()
+        .map(|(keypair, envelope, _)| {
+            let intent_data = envelope.pack();
+            let message = preimage(&program_id, &intent_data);
+            (
+                envelope.intent().from,
+                sign(keypair, &message),
+                message,
+                intent_data,
+            )
+        })
+        .collect();
+    let signatures: Vec<_> = signed
+        .iter()
+        .map(|(from, signature, message, _)| (from, signature, message.as_slice()))
+        .collect();
+
+    let mut accounts = vec![
+        AccountMeta::new(relayer, true),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(sysvar::instructions::ID, false),
+    ];
+    for ((_, envelope, extras), (_, _, _, intent_data)) in intents.iter().zip(&signed) {
+        let intent = envelope.intent();
+        let receipt = SettlementReceipt::find_address(&intent_hash(intent_data), &program_id).0;
+        accounts.extend([
+            AccountMeta::new(intent.to, false),
+            AccountMeta::new(
+                NonceTracker::find_address(&intent.from, &program_id).0,
+                false,
+            ),
+            AccountMeta::new(receipt, false),
+            AccountMeta::new(Vault::find_address(&intent.from, &program_id).0, false),
+        ]);
+        accounts.extend(extras.iter().cloned());
+    }
+
+    [
+        ed25519::new_ed25519_batch_instruction(&signatures),
+        Instruction {
+            program_id,
+            accounts,
+            data: borsh::to_vec(&TossIntentInstruction::ProcessIntentBatch { mode }).unwrap(),
+        },
+    ]
+}
+
+/// Submit `instructions` signed by the payer and return the batch results
+async fn process_batch(
+    test: &mut IntentTest,
+    instructions: &[Instruction],
+) -> Result<Vec<u64>, BanksClientError> {
+    let blockhash = test.context.get_new_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&test.context.payer.pubkey()),
+        &[&test.context.payer],
+        blockhash,
+    );
+    let outcome = test
+        .context
+        .banks_client
+        .process_transaction_with_metadata(transaction)
+        .await?;
+    outcome.result.map_err(BanksClientError::TransactionError)?;
+    let return_data = outcome.metadata.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, test.program_id);
+    Ok(Vec::<u64>::try_from_slice(&return_data.data).unwrap())
+}
+
+/// A bank with a second sender whose vault holds `SENDER_LAMPORTS`
+async fn start_with_second_sender() -> (IntentTest, Keypair) {
+    let mut test = IntentTest::start().await;
+    let second = Keypair::new();
+    let (address, bump) = Vault::find_address(&second.pubkey(), &test.program_id);
+    let vault = Account {
+        lamports: SENDER_LAMPORTS,
+        data: borsh::to_vec(&Vault::new(second.pubkey(), bump)).unwrap(),
+        owner: test.program_id,
+        ..Account::default()
+    };
+    test.context.set_account(&address, &vault.into());
+    (test, second)
+}
+
+#[tokio::test]
+async fn test_batch_settles_every_intent() {
+    let (mut test, second) = start_with_second_sender().await;
+    let first = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut other = intent(test.sender.pubkey(), Pubkey::new_unique(), 2_000_000);
+    other.nonce = 2;
+    let from_second = intent(second.pubkey(), Pubkey::new_unique(), 3_000_000);
+    let instructions = batch_instructions(
+        test.program_id,
+        &[
+            (&test.sender, &first),
+            (&test.sender, &other),
+            (&second, &from_second),
+        ],
+        test.context.payer.pubkey(),
+        BatchMode::AllOrNothing,
+    );
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(results, vec![0, 0, 0]);
+    assert_eq!(test.balance(first.to).await, 1_000_000);
+    assert_eq!(test.balance(other.to).await, 2_000_000);
+    assert_eq!(test.balance(from_second.to).await, 3_000_000);
+}
+
+#[tokio::test]
+async fn test_all_or_nothing_batch_fails_as_a_whole() {
+    let mut test = IntentTest::start().await;
+    let first = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut overdrawn = intent(test.sender.pubkey(), Pubkey::new_unique(), SENDER_LAMPORTS);
+    overdrawn.nonce = 2;
+    let instructions = batch_instructions(
+        test.program_id,
+        &[(&test.sender, &first), (&test.sender, &overdrawn)],
+        test.context.payer.pubkey(),
+        BatchMode::AllOrNothing,
+    );
+
+    let result = process_batch(&mut test, &instructions).await.map(|_| ());
+
+    assert_toss_error(result, 1, TossError::InsufficientFunds);
+    assert_eq!(test.balance(first.to).await, 0);
+}
+
+#[tokio::test]
+async fn test_skip_failed_batch_reports_skipped_intents() {
+    let mut test = IntentTest::start().await;
+    let settled = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    test.settle(&settled).await.unwrap();
+
+    let mut replayed = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    replayed.nonce = settled.nonce;
+    let mut overdrawn = intent(test.sender.pubkey(), Pubkey::new_unique(), SENDER_LAMPORTS);
+    overdrawn.nonce = 2;
+    let mut fresh = intent(test.sender.pubkey(), Pubkey::new_unique(), 2_000_000);
+    fresh.nonce = 3;
+    let instructions = batch_instructions(
+        test.program_id,
+        &[
+            (&test.sender, &replayed),
+            (&test.sender, &overdrawn),
+            (&test.sender, &fresh),
+        ],
+        test.context.payer.pubkey(),
+        BatchMode::SkipFailed,
+    );
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            TossError::NonceAlreadyUsed as u64,
+            TossError::InsufficientFunds as u64,
+            0,
+        ]
+    );
+    assert_eq!(test.balance(replayed.to).await, 0);
+    assert_eq!(test.balance(overdrawn.to).await, 0);
+    assert_eq!(test.balance(fresh.to).await, 2_000_000);
+
+    // The skipped intent's nonce stays free
+    overdrawn.amount = 1_000_000;
+    test.settle(&overdrawn).await.unwrap();
+    assert_eq!(test.balance(overdrawn.to).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_batch_intent_must_be_signed_by_its_sender() {
+    let (mut test, second) = start_with_second_sender().await;
+    let intent = intent(second.pubkey(), Pubkey::new_unique(), 1_000_000);
+    // A valid signature, but by the default sender over the second sender's
+    // intent
+    let message = preimage(&test.program_id, &pack(&intent));
+    let signature = sign(&test.sender, &message);
+    let mis_signed = |mode| {
+        let mut instructions = batch_instructions(
+            test.program_id,
+            &[(&test.sender, &intent)],
+            test.context.payer.pubkey(),
+            mode,
+        );
+        instructions[0] =
+            ed25519::new_ed25519_instruction(&test.sender.pubkey(), &signature, &message);
+        instructions
+    };
+    let all_or_nothing = mis_signed(BatchMode::AllOrNothing);
+    let skip_failed = mis_signed(BatchMode::SkipFailed);
+
+    let result = process_batch(&mut test, &all_or_nothing).await.map(|_| ());
+    assert_toss_error(result, 1, TossError::SignerMismatch);
+
+    let results = process_batch(&mut test, &skip_failed).await.unwrap();
+    assert_eq!(results, vec![TossError::SignerMismatch as u64]);
+    assert_eq!(test.balance(intent.to).await, 0);
+}
+
+#[tokio::test]
+async fn test_skip_failed_batch_skips_undecodable_intents() {
+    let mut test = IntentTest::start().await;
+    let foreign = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut settled = intent(test.sender.pubkey(), Pubkey::new_unique(), 2_000_000);
+    settled.nonce = 2;
+    let mut instructions = batch_instructions(
+        test.program_id,
+        &[(&test.sender, &settled)],
+        test.context.payer.pubkey(),
+        BatchMode::SkipFailed,
+    );
+    // The first intent is signed for another program and passes no
+    // accounts; the second still finds its own
+    let foreign_message = preimage(&Pubkey::new_unique(), &pack(&foreign));
+    let foreign_signature = sign(&test.sender, &foreign_message);
+    let message = preimage(&test.program_id, &pack(&settled));
+    let signature = sign(&test.sender, &message);
+    instructions[0] = ed25519::new_ed25519_batch_instruction(&[
+        (&foreign.from, &foreign_signature, &foreign_message),
+        (&settled.from, &signature, &message),
+    ]);
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(results, vec![TossError::MessageMismatch as u64, 0]);
+    assert_eq!(test.balance(foreign.to).await, 0);
+    assert_eq!(test.balance(settled.to).await, 2_000_000);
+}
+
+#[tokio::test]
+async fn test_skip_failed_batch_records_missing_accounts() {
+    let mut test = IntentTest::start().await;
+    let mut half_nonce = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    half_nonce.nonce_account = Some(Pubkey::new_unique());
+    let mut settled = intent(test.sender.pubkey(), Pubkey::new_unique(), 2_000_000);
+    settled.nonce = 2;
+    let mut missing = intent(test.sender.pubkey(), Pubkey::new_unique(), 3_000_000);
+    missing.nonce = 3;
+    let mut instructions = batch_instructions(
+        test.program_id,
+        &[
+            (&test.sender, &half_nonce),
+            (&test.sender, &settled),
+            (&test.sender, &missing),
+        ],
+        test.context.payer.pubkey(),
+        BatchMode::SkipFailed,
+    );
+    // The half-set nonce intent takes no accounts; the last intent is one
+    // short
+    instructions[1].accounts.drain(3..7);
+    instructions[1].accounts.pop();
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            TossError::IncompleteNonceConfig as u64,
+            0,
+            u64::from(ProgramError::NotEnoughAccountKeys)
+        ]
+    );
+    assert_eq!(test.balance(half_nonce.to).await, 0);
+    assert_eq!(test.balance(settled.to).await, 2_000_000);
+    assert_eq!(test.balance(missing.to).await, 0);
+}
+
+#[tokio::test]
+async fn test_batch_requires_signature_instruction() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let instructions = batch_instructions(
+        test.program_id,
+        &[(&test.sender, &intent)],
+        test.context.payer.pubkey(),
+        BatchMode::AllOrNothing,
+    );
+
+    let result = process_batch(&mut test, &instructions[1..])
+        .await
+        .map(|_| ());
+
+    assert_toss_error(result, 0, TossError::MissingSignatureInstruction);
+}
+
+#[tokio::test]
+async fn test_batch_reads_sender_keys_from_preimages() {
+    let (mut test, second) = start_with_second_sender().await;
+    let first = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let from_second = intent(second.pubkey(), Pubkey::new_unique(), 2_000_000);
+    let mut instructions = batch_instructions(
+        test.program_id,
+        &[(&test.sender, &first), (&second, &from_second)],
+        test.context.payer.pubkey(),
+        BatchMode::AllOrNothing,
+    );
+    let signed: Vec<_> = [(&test.sender, &first), (&second, &from_second)]
+        .iter()
+        .map(|(keypair, intent)| {
+            let message = preimage(&test.program_id, &pack(intent));
+            (sign(keypair, &message), message)
+        })
+        .collect();
+    let signatures: Vec<_> = signed
+        .iter()
+        .map(|(signature, message)| (signature, message.as_slice()))
+        .collect();
+    instructions[0] = ed25519::new_ed25519_intent_batch_instruction(&signatures);
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(results, vec![0, 0]);
+    assert_eq!(test.balance(first.to).await, 1_000_000);
+    assert_eq!(test.balance(from_second.to).await, 2_000_000);
+}
+
+#[tokio::test]
+async fn test_batch_pays_relayer_fees_and_mixes_denominations() {
+    let mint = Pubkey::new_unique();
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let token_recipient = Pubkey::new_unique();
+    let mut test = IntentTest::start_with(|program_test, vault| {
+        add_mint(program_test, mint, 6);
+        add_token_account(program_test, source, mint, *vault, 5_000_000);
+        add_token_account(program_test, destination, mint, token_recipient, 0);
+    })
+    .await;
+    let fee = IntentExtension::RelayerFee(RelayerFee {
+        amount: 10_000,
+        relayer: None,
+    });
+    let lamports = IntentEnvelope::V2(SolanaIntentV2 {
+        intent: intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000),
+        extensions: vec![fee.clone()],
+    });
+    let mut token_intent = intent(test.sender.pubkey(), token_recipient, 2_000_000);
+    token_intent.nonce = 2;
+    let tokens = IntentEnvelope::V2(SolanaIntentV2 {
+        intent: token_intent,
+        extensions: vec![
+            IntentExtension::Token(TokenTransfer { mint, decimals: 6 }),
+            fee,
+        ],
+    });
+    let token_accounts = [
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(source, false),
+        AccountMeta::new(destination, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+    ];
+    let vault_before = test.balance(test.vault).await;
+    // The vault's lamport debit comes before its token transfer CPI
+    let instructions = batch_envelope_instructions(
+        test.program_id,
+        &[
+            (&test.sender, &lamports, &[]),
+            (&test.sender, &tokens, &token_accounts),
+        ],
+        test.context.payer.pubkey(),
+        BatchMode::AllOrNothing,
+    );
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(results, vec![0, 0]);
+    assert_eq!(test.balance(lamports.intent().to).await, 1_000_000);
+    assert_eq!(
+        test.balance(test.vault).await,
+        vault_before - 1_000_000 - 20_000
+    );
+    let account = test
+        .context
+        .banks_client
+        .get_account(destination)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TokenAccount::unpack(&account.data).unwrap().amount,
+        2_000_000
+    );
+}
+
+#[tokio::test]
+async fn test_batch_checks_funds_against_earlier_intents() {
+    let mut test = IntentTest::start().await;
+    let rent = test
+        .context
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(Vault::LEN);
+    let spendable = test.balance(test.vault).await - rent;
+    let first = intent(test.sender.pubkey(), Pubkey::new_unique(), spendable - 1);
+    let mut second = intent(test.sender.pubkey(), Pubkey::new_unique(), 2);
+    second.nonce = 2;
+    let instructions = batch_instructions(
+        test.program_id,
+        &[(&test.sender, &first), (&test.sender, &second)],
+        test.context.payer.pubkey(),
+        BatchMode::SkipFailed,
+    );
+
+    let results = process_batch(&mut test, &instructions).await.unwrap();
+
+    assert_eq!(results, vec![0, TossError::InsufficientFunds as u64]);
+    assert_eq!(test.balance(first.to).await, spendable - 1);
+}
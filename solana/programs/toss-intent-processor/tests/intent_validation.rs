@@ -0,0 +1,153 @@
+mod common;
+
+use common::*;
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, signature::Signer};
+use toss_intent_processor::{
+    ed25519,
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2},
+    SolanaIntent,
+};
+
+fn with_extensions(intent: SolanaIntent, extensions: Vec<IntentExtension>) -> IntentEnvelope {
+    IntentEnvelope::V2(SolanaIntentV2 { intent, extensions })
+}
+
+#[tokio::test]
+async fn test_expired_intent_is_rejected() {
+    let mut test = IntentTest::start().await;
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.expiry = 1;
+
+    let result = test.settle(&intent).await;
+
+    let error = assert_toss_error(result, 1, TossError::IntentExpired);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_post_dated_intent_is_retryable() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let envelope = with_extensions(intent, vec![IntentExtension::ValidAfter(i64::MAX as u64)]);
+
+    let result = test.settle_envelope(&envelope, &[]).await;
+
+    let error = assert_toss_error(result, 1, TossError::IntentNotYetValid);
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_intent_past_expiry_slot_is_rejected() {
+    let mut test = IntentTest::start().await;
+    test.context.warp_to_slot(100).unwrap();
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let envelope = with_extensions(intent, vec![IntentExtension::ExpirySlot(50)]);
+
+    let result = test.settle_envelope(&envelope, &[]).await;
+
+    assert_toss_error(result, 1, TossError::IntentExpired);
+}
+
+#[tokio::test]
+async fn test_intent_settles_inside_validity_window() {
+    let mut test = IntentTest::start().await;
+    test.context.warp_to_slot(100).unwrap();
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let recipient = intent.to;
+    let envelope = with_extensions(
+        intent,
+        vec![
+            IntentExtension::ValidAfter(1),
+            IntentExtension::ValidAfterSlot(50),
+            IntentExtension::ExpirySlot(1_000),
+        ],
+    );
+
+    test.settle_envelope(&envelope, &[]).await.unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_recipient_account_must_match_intent() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let intent_data = pack(&intent);
+    let message = preimage(&test.program_id, &intent_data);
+    let signature = sign(&test.sender, &message);
+
+    let mut ix = process_intent_ix(
+        test.program_id,
+        &intent,
+        signature,
+        intent_data,
+        test.context.payer.pubkey(),
+    );
+    ix.accounts[1] = AccountMeta::new(Pubkey::new_unique(), false);
+    let instructions = [
+        ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+        ix,
+    ];
+    let result = test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::RecipientMismatch);
+}
+
+#[tokio::test]
+async fn test_underfunded_sender_is_retryable() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(
+        test.sender.pubkey(),
+        Pubkey::new_unique(),
+        SENDER_LAMPORTS * 2,
+    );
+
+    let result = test.settle(&intent).await;
+
+    let error = assert_toss_error(result, 1, TossError::InsufficientFunds);
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_unknown_intent_version_is_rejected() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut intent_data = pack(&intent);
+    intent_data[0] = 0x7F;
+
+    let ix = process_intent_ix(
+        test.program_id,
+        &intent,
+        [0u8; 64],
+        intent_data,
+        test.context.payer.pubkey(),
+    );
+    let result = test.process(&[ix], &[]).await;
+
+    assert_toss_error(result, 0, TossError::UnsupportedIntentVersion);
+}
+
+#[tokio::test]
+async fn test_unprefixed_legacy_intent_settles_as_v1() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let recipient = intent.to;
+    let intent_data = borsh::to_vec(&intent).unwrap();
+    let message = preimage(&test.program_id, &intent_data);
+    let signature = sign(&test.sender, &message);
+
+    let instructions = [
+        ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+        process_intent_ix(
+            test.program_id,
+            &intent,
+            signature,
+            intent_data,
+            test.context.payer.pubkey(),
+        ),
+    ];
+    test.process(&instructions, &[]).await.unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+}
@@ -0,0 +1,276 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_program_test::{BanksClientError, ProgramTest};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+};
+use spl_token_2022::{
+    extension::{transfer_fee::TransferFee, StateWithExtensions},
+    state::Account as TokenAccount,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{
+        IntentEnvelope, IntentExtension, SolanaIntentV2, TokenTransfer, TransferFeeMode,
+        TransferFeeTerms,
+    },
+    intent_hash,
+    state::{Invoice, InvoiceStatus},
+    TossIntentInstruction,
+};
+
+const INVOICE_ID: u64 = 42;
+const AMOUNT: u64 = 1_000_000;
+
+/// A bank with a funded payee that has opened invoice `INVOICE_ID`
+struct InvoiceTest {
+    test: IntentTest,
+    payee: Keypair,
+    invoice: Pubkey,
+}
+
+impl InvoiceTest {
+    async fn start() -> Self {
+        Self::start_with(Keypair::new(), system_program::ID, |_, _| {}).await
+    }
+
+    /// Like `start`, with the invoice asking for `mint` and `configure`
+    /// adding accounts to the bank as for `IntentTest::start_with`
+    async fn start_with(
+        payee: Keypair,
+        mint: Pubkey,
+        configure: impl FnOnce(&mut ProgramTest, &Pubkey),
+    ) -> Self {
+        let mut test = IntentTest::start_with(configure).await;
+        let payer = test.context.payer.pubkey();
+        let fund = system_instruction::transfer(&payer, &payee.pubkey(), 1_000_000_000);
+        test.process(&[fund], &[]).await.unwrap();
+        let invoice = Invoice::find_address(&payee.pubkey(), INVOICE_ID, &test.program_id).0;
+        let mut invoice_test = Self {
+            test,
+            payee,
+            invoice,
+        };
+        let create = invoice_test.instruction(
+            TossIntentInstruction::CreateInvoice {
+                invoice_id: INVOICE_ID,
+                amount: AMOUNT,
+                mint,
+                expiry: i64::MAX,
+                memo: "order #1042".into(),
+            },
+            vec![AccountMeta::new_readonly(system_program::ID, false)],
+        );
+        invoice_test.process(create).await.unwrap();
+        invoice_test
+    }
+
+    /// Invoice instruction signed by the payee, with `extra` accounts after
+    /// the invoice
+    fn instruction(
+        &self,
+        instruction: TossIntentInstruction,
+        extra: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(self.payee.pubkey(), true),
+            AccountMeta::new(self.invoice, false),
+        ];
+        accounts.extend(extra);
+        Instruction {
+            program_id: self.test.program_id,
+            accounts,
+            data: borsh::to_vec(&instruction).unwrap(),
+        }
+    }
+
+    async fn process(&mut self, instruction: Instruction) -> Result<(), BanksClientError> {
+        self.test.process(&[instruction], &[&self.payee]).await
+    }
+
+    /// Intent from the sender paying `amount` against the invoice
+    fn paying(&self, amount: u64, nonce: u64) -> IntentEnvelope {
+        let mut intent = intent(self.test.sender.pubkey(), self.payee.pubkey(), amount);
+        intent.nonce = nonce;
+        IntentEnvelope::V2(SolanaIntentV2 {
+            intent,
+            extensions: vec![IntentExtension::Invoice(INVOICE_ID)],
+        })
+    }
+
+    async fn settle(&mut self, envelope: &IntentEnvelope) -> Result<(), BanksClientError> {
+        let invoice = self.invoice;
+        self.test
+            .settle_with_accounts(envelope, &[AccountMeta::new(invoice, false)], &[])
+            .await
+    }
+
+    async fn state(&mut self) -> Option<Invoice> {
+        self.test
+            .context
+            .banks_client
+            .get_account(self.invoice)
+            .await
+            .unwrap()
+            .map(|account| Invoice::try_from_slice(&account.data).unwrap())
+    }
+}
+
+#[tokio::test]
+async fn test_intent_pays_invoice_once() {
+    let mut test = InvoiceTest::start().await;
+    let payee_before = test.test.balance(test.payee.pubkey()).await;
+    let envelope = test.paying(AMOUNT, 1);
+
+    test.settle(&envelope).await.unwrap();
+
+    let invoice = test.state().await.unwrap();
+    assert_eq!(invoice.status, InvoiceStatus::Paid);
+    assert_eq!(invoice.paid_by, test.test.sender.pubkey());
+    assert_eq!(invoice.paid_intent_hash, intent_hash(&envelope.pack()));
+    assert_eq!(invoice.memo, "order #1042");
+    assert_eq!(
+        test.test.balance(test.payee.pubkey()).await,
+        payee_before + AMOUNT
+    );
+
+    let result = test.settle(&test.paying(AMOUNT, 2)).await;
+    assert_toss_error(result, 1, TossError::InvoiceNotOpen);
+}
+
+#[tokio::test]
+async fn test_intent_must_match_invoice_amount() {
+    let mut test = InvoiceTest::start().await;
+
+    let result = test.settle(&test.paying(AMOUNT - 1, 1)).await;
+
+    assert_toss_error(result, 1, TossError::InvoiceMismatch);
+    assert_eq!(test.state().await.unwrap().status, InvoiceStatus::Open);
+}
+
+#[tokio::test]
+async fn test_token_invoice_counts_what_payee_receives() {
+    let transfer_fee = TransferFee {
+        epoch: 0.into(),
+        maximum_fee: u64::MAX.into(),
+        transfer_fee_basis_points: 100.into(),
+    };
+    let payee = Keypair::new();
+    let payee_key = payee.pubkey();
+    let (mint, source, destination) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let mut test = InvoiceTest::start_with(payee, mint, |program_test, vault| {
+        add_token_2022_mint(program_test, mint, 6, Some(transfer_fee));
+        add_token_2022_account(program_test, source, mint, *vault, 10 * AMOUNT, false);
+        add_token_2022_account(program_test, destination, mint, payee_key, 0, false);
+    })
+    .await;
+    let accounts = [
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(source, false),
+        AccountMeta::new(destination, false),
+        AccountMeta::new_readonly(spl_token_2022::ID, false),
+        AccountMeta::new(test.invoice, false),
+    ];
+    let paying_tokens = |test: &InvoiceTest, mode, max_fee| {
+        let IntentEnvelope::V2(mut intent) = test.paying(AMOUNT, 1) else {
+            unreachable!()
+        };
+        intent.extensions.extend([
+            IntentExtension::Token(TokenTransfer { mint, decimals: 6 }),
+            IntentExtension::TransferFee(TransferFeeTerms { mode, max_fee }),
+        ]);
+        IntentEnvelope::V2(intent)
+    };
+
+    // The fee comes out of a gross intent's amount, so the payee is short
+    let gross_fee = transfer_fee.calculate_fee(AMOUNT).unwrap();
+    let gross = paying_tokens(&test, TransferFeeMode::Gross, gross_fee);
+    let result = test.test.settle_with_accounts(&gross, &accounts, &[]).await;
+    assert_toss_error(result, 1, TossError::InvoiceMismatch);
+    assert_eq!(test.state().await.unwrap().status, InvoiceStatus::Open);
+
+    let net_fee = transfer_fee.calculate_inverse_fee(AMOUNT).unwrap();
+    let net = paying_tokens(&test, TransferFeeMode::Net, net_fee);
+    test.test
+        .settle_with_accounts(&net, &accounts, &[])
+        .await
+        .unwrap();
+
+    assert_eq!(test.state().await.unwrap().status, InvoiceStatus::Paid);
+    let account = test
+        .test
+        .context
+        .banks_client
+        .get_account(destination)
+        .await
+        .unwrap()
+        .unwrap();
+    let received = StateWithExtensions::<TokenAccount>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount;
+    assert_eq!(received, AMOUNT);
+}
+
+#[tokio::test]
+async fn test_cancelled_invoice_cannot_be_paid() {
+    let mut test = InvoiceTest::start().await;
+    let cancel = test.instruction(
+        TossIntentInstruction::CancelInvoice {
+            invoice_id: INVOICE_ID,
+        },
+        vec![],
+    );
+    test.process(cancel).await.unwrap();
+
+    let result = test.settle(&test.paying(AMOUNT, 1)).await;
+
+    assert_toss_error(result, 1, TossError::InvoiceNotOpen);
+}
+
+#[tokio::test]
+async fn test_invoice_closes_once_paid() {
+    let mut test = InvoiceTest::start().await;
+    let destination = Pubkey::new_unique();
+    let close = test.instruction(
+        TossIntentInstruction::CloseInvoice {
+            invoice_id: INVOICE_ID,
+        },
+        vec![AccountMeta::new(destination, false)],
+    );
+    let rent = test.test.balance(test.invoice).await;
+
+    let result = test.process(close.clone()).await;
+    assert_toss_error(result, 0, TossError::InvoiceStillOpen);
+
+    test.settle(&test.paying(AMOUNT, 1)).await.unwrap();
+    test.process(close).await.unwrap();
+
+    assert!(test.state().await.is_none());
+    assert_eq!(test.test.balance(destination).await, rent);
+}
+
+#[tokio::test]
+async fn test_only_payee_can_cancel_invoice() {
+    let mut test = InvoiceTest::start().await;
+    let mut cancel = test.instruction(
+        TossIntentInstruction::CancelInvoice {
+            invoice_id: INVOICE_ID,
+        },
+        vec![],
+    );
+    cancel.accounts[0] = AccountMeta::new(test.test.context.payer.pubkey(), true);
+
+    let result = test.test.process(&[cancel], &[]).await;
+
+    assert_toss_error(result, 0, TossError::InvalidInvoice);
+}
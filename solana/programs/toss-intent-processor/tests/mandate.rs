@@ -0,0 +1,226 @@
+mod common;
+
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+};
+use toss_intent_processor::{
+    ed25519,
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, MandateTerms, SolanaIntentV2},
+    intent_hash,
+    state::{Mandate, NonceTracker},
+    TossIntentInstruction,
+};
+
+const PER_PERIOD: u64 = 1_000_000;
+const PERIOD: u64 = 30 * 24 * 60 * 60;
+
+/// A bank with a funded payee holding a two-period mandate from the sender
+struct MandateTest {
+    test: IntentTest,
+    payee: Keypair,
+    envelope: IntentEnvelope,
+    mandate: Pubkey,
+}
+
+impl MandateTest {
+    async fn start() -> Self {
+        let mut test = IntentTest::start().await;
+        let payee = Keypair::new();
+        let payer = test.context.payer.pubkey();
+        let fund = system_instruction::transfer(&payer, &payee.pubkey(), 1_000_000_000);
+        test.process(&[fund], &[]).await.unwrap();
+        let envelope = IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(test.sender.pubkey(), payee.pubkey(), PER_PERIOD),
+            extensions: vec![IntentExtension::Mandate(MandateTerms {
+                period: PERIOD,
+                periods: 2,
+            })],
+        });
+        let mandate = Mandate::find_address(
+            &test.sender.pubkey(),
+            &intent_hash(&envelope.pack()),
+            &test.program_id,
+        )
+        .0;
+        Self {
+            test,
+            payee,
+            envelope,
+            mandate,
+        }
+    }
+
+    async fn activate(&mut self) -> Result<(), BanksClientError> {
+        let intent_data = self.envelope.pack();
+        let message = preimage(&self.test.program_id, &intent_data);
+        let signature = sign(&self.test.sender, &message);
+        let activate = Instruction {
+            program_id: self.test.program_id,
+            accounts: vec![
+                AccountMeta::new(self.payee.pubkey(), true),
+                AccountMeta::new(self.mandate, false),
+                AccountMeta::new(
+                    NonceTracker::find_address(&self.test.sender.pubkey(), &self.test.program_id).0,
+                    false,
+                ),
+                AccountMeta::new_readonly(sysvar::instructions::ID, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: borsh::to_vec(&TossIntentInstruction::ActivateMandate {
+                signature,
+                intent_data,
+            })
+            .unwrap(),
+        };
+        let verify =
+            ed25519::new_ed25519_instruction(&self.test.sender.pubkey(), &signature, &message);
+        self.test.process(&[verify, activate], &[&self.payee]).await
+    }
+
+    async fn pull(&mut self, amount: u64) -> Result<(), BanksClientError> {
+        let pull = Instruction {
+            program_id: self.test.program_id,
+            accounts: vec![
+                AccountMeta::new(self.payee.pubkey(), true),
+                AccountMeta::new(self.mandate, false),
+                AccountMeta::new(self.test.vault, false),
+            ],
+            data: borsh::to_vec(&TossIntentInstruction::PullMandate { amount }).unwrap(),
+        };
+        self.test.process(&[pull], &[&self.payee]).await
+    }
+
+    async fn revoke(&mut self) -> Result<(), BanksClientError> {
+        let revoke = Instruction {
+            program_id: self.test.program_id,
+            accounts: vec![
+                AccountMeta::new(self.test.sender.pubkey(), true),
+                AccountMeta::new(self.mandate, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: borsh::to_vec(&TossIntentInstruction::RevokeMandate {
+                intent_hash: intent_hash(&self.envelope.pack()),
+            })
+            .unwrap(),
+        };
+        self.test.process(&[revoke], &[]).await
+    }
+}
+
+#[tokio::test]
+async fn test_payee_pulls_up_to_cap_each_period() {
+    let mut test = MandateTest::start().await;
+    test.activate().await.unwrap();
+    let payee_before = test.test.balance(test.payee.pubkey()).await;
+
+    test.pull(600_000).await.unwrap();
+    test.pull(400_000).await.unwrap();
+    let result = test.pull(1).await;
+    assert_toss_error(result, 0, TossError::MandateAmountExceeded);
+
+    test.test.advance_clock(PERIOD as i64).await;
+    test.pull(PER_PERIOD).await.unwrap();
+    assert_eq!(
+        test.test.balance(test.payee.pubkey()).await,
+        payee_before + 2 * PER_PERIOD
+    );
+
+    test.test.advance_clock(PERIOD as i64).await;
+    let result = test.pull(1).await;
+    assert_toss_error(result, 0, TossError::MandateEnded);
+}
+
+#[tokio::test]
+async fn test_payer_revokes_active_mandate() {
+    let mut test = MandateTest::start().await;
+    test.activate().await.unwrap();
+    test.pull(PER_PERIOD).await.unwrap();
+
+    test.revoke().await.unwrap();
+
+    test.test.advance_clock(PERIOD as i64).await;
+    let result = test.pull(1).await;
+    assert_toss_error(result, 0, TossError::MandateRevoked);
+}
+
+#[tokio::test]
+async fn test_revoked_mandate_cannot_be_activated() {
+    let mut test = MandateTest::start().await;
+
+    test.revoke().await.unwrap();
+
+    let result = test.activate().await;
+    assert_toss_error(result, 1, TossError::MandateRevoked);
+}
+
+#[tokio::test]
+async fn test_mandate_activates_once() {
+    let mut test = MandateTest::start().await;
+    test.activate().await.unwrap();
+
+    let result = test.activate().await;
+
+    assert_toss_error(result, 1, TossError::MandateAlreadyActive);
+}
+
+#[tokio::test]
+async fn test_mandate_is_not_a_one_off_payment() {
+    let mut test = MandateTest::start().await;
+    let envelope = test.envelope.clone();
+
+    let result = test.test.settle_envelope(&envelope, &[]).await;
+
+    assert_toss_error(result, 1, TossError::InvalidMandate);
+}
+
+#[tokio::test]
+async fn test_activation_uses_up_the_nonce() {
+    let mut test = MandateTest::start().await;
+    test.activate().await.unwrap();
+    let payment = intent(test.test.sender.pubkey(), Pubkey::new_unique(), PER_PERIOD);
+
+    let result = test.test.settle(&payment).await;
+
+    assert_toss_error(result, 1, TossError::NonceAlreadyUsed);
+    assert_eq!(test.test.balance(payment.to).await, 0);
+}
+
+#[tokio::test]
+async fn test_mandate_cannot_reuse_a_settled_nonce() {
+    let mut test = MandateTest::start().await;
+    let payment = intent(test.test.sender.pubkey(), Pubkey::new_unique(), PER_PERIOD);
+    test.test.settle(&payment).await.unwrap();
+
+    let result = test.activate().await;
+
+    assert_toss_error(result, 1, TossError::NonceAlreadyUsed);
+}
+
+#[tokio::test]
+async fn test_mandate_cannot_use_a_durable_nonce() {
+    let mut test = MandateTest::start().await;
+    let nonce_account = Pubkey::new_unique();
+    let IntentEnvelope::V2(mandate) = &mut test.envelope else {
+        unreachable!()
+    };
+    mandate.intent.nonce_account = Some(nonce_account);
+
+    let result = test.activate().await;
+    assert_toss_error(result, 1, TossError::InvalidIntentExtension);
+
+    let IntentEnvelope::V2(mandate) = &mut test.envelope else {
+        unreachable!()
+    };
+    mandate.intent.nonce_auth = Some(test.test.sender.pubkey());
+    mandate
+        .extensions
+        .push(IntentExtension::DurableNonce([7; 32]));
+    let result = test.activate().await;
+    assert_toss_error(result, 1, TossError::InvalidIntentExtension);
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2},
+    TossIntentInstruction,
+};
+
+fn referenced(test: &IntentTest, references: &[Pubkey]) -> IntentEnvelope {
+    IntentEnvelope::V2(SolanaIntentV2 {
+        intent: intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000),
+        extensions: vec![
+            IntentExtension::Memo("order #1042".into()),
+            IntentExtension::References(references.to_vec()),
+        ],
+    })
+}
+
+fn reference_accounts(envelope: &IntentEnvelope) -> Vec<AccountMeta> {
+    envelope
+        .references()
+        .iter()
+        .map(|reference| AccountMeta::new_readonly(*reference, false))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_settlement_logs_memo_and_lists_references() {
+    let mut test = IntentTest::start().await;
+    let references = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let envelope = referenced(&test, &references);
+    let mut instructions = test.settlement_instructions(&envelope);
+    instructions[1]
+        .accounts
+        .extend(reference_accounts(&envelope));
+    let blockhash = test.context.get_new_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&test.context.payer.pubkey()),
+        &[&test.context.payer],
+        blockhash,
+    );
+
+    let outcome = test
+        .context
+        .banks_client
+        .process_transaction_with_metadata(transaction.clone())
+        .await
+        .unwrap();
+
+    outcome.result.unwrap();
+    let logs = outcome.metadata.unwrap().log_messages;
+    assert!(logs.iter().any(|log| log.ends_with("Memo: order #1042")));
+    for reference in references {
+        assert!(transaction.message.account_keys.contains(&reference));
+    }
+    assert_eq!(test.balance(envelope.intent().to).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_reference_accounts_must_match_intent() {
+    let mut test = IntentTest::start().await;
+    let envelope = referenced(&test, &[Pubkey::new_unique()]);
+
+    let result = test
+        .settle_with_accounts(
+            &envelope,
+            &[AccountMeta::new_readonly(Pubkey::new_unique(), false)],
+            &[],
+        )
+        .await;
+
+    assert_toss_error(result, 1, TossError::ReferenceMismatch);
+}
+
+#[tokio::test]
+async fn test_reference_accounts_must_be_read_only_and_unsigned() {
+    let mut test = IntentTest::start().await;
+    let reference = Keypair::new();
+    let envelope = referenced(&test, &[reference.pubkey()]);
+
+    let writable = [AccountMeta::new(reference.pubkey(), false)];
+    let result = test.settle_with_accounts(&envelope, &writable, &[]).await;
+    assert_toss_error(result, 1, TossError::ReferenceMismatch);
+
+    let signer = [AccountMeta::new_readonly(reference.pubkey(), true)];
+    let result = test
+        .settle_with_accounts(&envelope, &signer, &[&reference])
+        .await;
+    assert_toss_error(result, 1, TossError::ReferenceMismatch);
+    assert_eq!(test.balance(envelope.intent().to).await, 0);
+}
+
+#[tokio::test]
+async fn test_memo_is_covered_by_the_signature() {
+    let mut test = IntentTest::start().await;
+    let envelope = referenced(&test, &[Pubkey::new_unique()]);
+    let mut instructions = test.settlement_instructions(&envelope);
+    instructions[1]
+        .accounts
+        .extend(reference_accounts(&envelope));
+    let altered = IntentEnvelope::V2(SolanaIntentV2 {
+        intent: envelope.intent().clone(),
+        extensions: vec![
+            IntentExtension::Memo("order #9999".into()),
+            IntentExtension::References(envelope.references().to_vec()),
+        ],
+    });
+    let TossIntentInstruction::ProcessIntent { signature, .. } =
+        TossIntentInstruction::try_from_slice(&instructions[1].data).unwrap()
+    else {
+        unreachable!()
+    };
+    instructions[1].data = borsh::to_vec(&TossIntentInstruction::ProcessIntent {
+        signature,
+        intent_data: altered.pack(),
+    })
+    .unwrap();
+
+    let result = test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::MessageMismatch);
+}
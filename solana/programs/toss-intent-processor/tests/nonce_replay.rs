@@ -0,0 +1,123 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::{pubkey::Pubkey, signature::Signer, system_program};
+use toss_intent_processor::{
+    error::TossError,
+    state::{NonceTracker, DEFAULT_NONCE_WINDOW},
+    TossIntentInstruction,
+};
+
+#[tokio::test]
+async fn test_first_intent_creates_nonce_tracker() {
+    let mut test = IntentTest::start().await;
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce = 7;
+
+    test.settle(&intent).await.unwrap();
+
+    let (tracker_address, bump) = NonceTracker::find_address(&intent.from, &test.program_id);
+    let account = test
+        .context
+        .banks_client
+        .get_account(tracker_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.owner, test.program_id);
+    let tracker = NonceTracker::try_from_slice(&account.data).unwrap();
+    assert_eq!(tracker.owner, intent.from);
+    assert_eq!(tracker.bump, bump);
+    assert_eq!(tracker.highest_nonce, 7);
+    assert_eq!(tracker.window_size, DEFAULT_NONCE_WINDOW);
+}
+
+#[tokio::test]
+async fn test_reused_nonce_is_rejected() {
+    let mut test = IntentTest::start().await;
+    let recipient = Pubkey::new_unique();
+    let mut intent = intent(test.sender.pubkey(), recipient, 1_000_000);
+
+    test.settle(&intent).await.unwrap();
+    // A different intent signed with the same nonce
+    intent.amount = 2_000_000;
+    let result = test.settle(&intent).await;
+
+    assert_toss_error(result, 1, TossError::NonceAlreadyUsed);
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+}
+
+fn configure_nonce_window_ix(test: &IntentTest, window_size: u16) -> Instruction {
+    let sender = test.sender.pubkey();
+    Instruction {
+        program_id: test.program_id,
+        accounts: vec![
+            AccountMeta::new(sender, true),
+            AccountMeta::new(
+                NonceTracker::find_address(&sender, &test.program_id).0,
+                false,
+            ),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        data: borsh::to_vec(&TossIntentInstruction::ConfigureNonceWindow { window_size }).unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_out_of_order_nonces_settle() {
+    let mut test = IntentTest::start().await;
+    let recipient = Pubkey::new_unique();
+    let mut intent = intent(test.sender.pubkey(), recipient, 1_000_000);
+
+    for nonce in [5, 3, 4] {
+        intent.nonce = nonce;
+        test.settle(&intent).await.unwrap();
+    }
+
+    intent.nonce = 3;
+    intent.amount = 2_000_000;
+    let result = test.settle(&intent).await;
+    assert_toss_error(result, 1, TossError::NonceAlreadyUsed);
+    assert_eq!(test.balance(recipient).await, 3_000_000);
+}
+
+#[tokio::test]
+async fn test_nonce_below_configured_window_is_stale() {
+    let mut test = IntentTest::start().await;
+    let ix = configure_nonce_window_ix(&test, 2);
+    test.process(&[ix], &[]).await.unwrap();
+
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce = 2;
+    test.settle(&intent).await.unwrap();
+
+    intent.nonce = 1;
+    test.settle(&intent).await.unwrap();
+
+    intent.nonce = 0;
+    let result = test.settle(&intent).await;
+    assert_toss_error(result, 1, TossError::NonceTooOld);
+}
+
+#[tokio::test]
+async fn test_nonce_beyond_window_is_rejected() {
+    let mut test = IntentTest::start().await;
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce = DEFAULT_NONCE_WINDOW as u64 + 1;
+
+    let result = test.settle(&intent).await;
+    assert_toss_error(result, 1, TossError::NonceTooFarAhead);
+
+    intent.nonce = DEFAULT_NONCE_WINDOW as u64;
+    test.settle(&intent).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_nonce_window_size_is_bounded() {
+    let mut test = IntentTest::start().await;
+    let ix = configure_nonce_window_ix(&test, 0);
+    let result = test.process(&[ix], &[]).await;
+    assert_toss_error(result, 0, TossError::InvalidNonceWindow);
+}
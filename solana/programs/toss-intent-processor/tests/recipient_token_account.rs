@@ -0,0 +1,203 @@
+mod common;
+
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::AccountMeta, program_pack::Pack, pubkey::Pubkey, signature::Signer,
+    system_instruction,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::Account as TokenAccount;
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, RentPayer, SolanaIntentV2, TokenTransfer},
+};
+
+const DECIMALS: u8 = 6;
+const SOURCE_BALANCE: u64 = 5_000_000;
+
+/// A bank with a mint and a funded vault token account, paying a recipient
+/// that holds no account for the mint
+struct RecipientTest {
+    test: IntentTest,
+    mint: Pubkey,
+    source: Pubkey,
+    recipient: Pubkey,
+}
+
+impl RecipientTest {
+    async fn start() -> Self {
+        let mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let test = IntentTest::start_with(|program_test, vault| {
+            add_mint(program_test, mint, DECIMALS);
+            add_token_account(program_test, source, mint, *vault, SOURCE_BALANCE);
+        })
+        .await;
+        Self {
+            test,
+            mint,
+            source,
+            recipient: Pubkey::new_unique(),
+        }
+    }
+
+    fn envelope(&self, nonce: u64, rent_payer: RentPayer) -> IntentEnvelope {
+        let mut intent = intent(self.test.sender.pubkey(), self.recipient, 1_000_000);
+        intent.nonce = nonce;
+        IntentEnvelope::V2(SolanaIntentV2 {
+            intent,
+            extensions: vec![
+                IntentExtension::Token(TokenTransfer {
+                    mint: self.mint,
+                    decimals: DECIMALS,
+                }),
+                IntentExtension::CreateRecipientTokenAccount(rent_payer),
+            ],
+        })
+    }
+
+    fn destination(&self) -> Pubkey {
+        get_associated_token_address(&self.recipient, &self.mint)
+    }
+
+    async fn settle_to(
+        &mut self,
+        envelope: &IntentEnvelope,
+        destination: Pubkey,
+    ) -> Result<(), BanksClientError> {
+        let accounts = [
+            AccountMeta::new_readonly(self.mint, false),
+            AccountMeta::new(self.source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(spl_associated_token_account::ID, false),
+        ];
+        let mut instructions = self.test.settlement_instructions(envelope);
+        instructions[1].accounts.splice(8..8, accounts);
+        // Only a sender paying rent has to sign the settlement
+        instructions[1].accounts[0].is_signer =
+            envelope.recipient_token_account_payer() == Some(RentPayer::Sender);
+        self.test.process(&instructions, &[]).await
+    }
+
+    async fn settle(&mut self, envelope: &IntentEnvelope) -> Result<(), BanksClientError> {
+        self.settle_to(envelope, self.destination()).await
+    }
+
+    async fn token_balance(&mut self, address: Pubkey) -> u64 {
+        let account = self
+            .test
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        TokenAccount::unpack(&account.data).unwrap().amount
+    }
+
+    async fn minimum_balance(&mut self, len: usize) -> u64 {
+        let rent = self.test.context.banks_client.get_rent().await.unwrap();
+        rent.minimum_balance(len)
+    }
+}
+
+#[tokio::test]
+async fn test_missing_recipient_token_account_is_created() {
+    let mut test = RecipientTest::start().await;
+    let envelope = test.envelope(1, RentPayer::Relayer);
+
+    test.settle(&envelope).await.unwrap();
+
+    let destination = test.destination();
+    assert_eq!(test.token_balance(destination).await, 1_000_000);
+    assert_eq!(
+        test.token_balance(test.source).await,
+        SOURCE_BALANCE - 1_000_000
+    );
+}
+
+#[tokio::test]
+async fn test_existing_recipient_token_account_is_reused() {
+    let mut test = RecipientTest::start().await;
+
+    test.settle(&test.envelope(1, RentPayer::Relayer))
+        .await
+        .unwrap();
+    test.settle(&test.envelope(2, RentPayer::Relayer))
+        .await
+        .unwrap();
+
+    let destination = test.destination();
+    assert_eq!(test.token_balance(destination).await, 2_000_000);
+}
+
+#[tokio::test]
+async fn test_sender_can_pay_recipient_account_rent() {
+    let mut test = RecipientTest::start().await;
+    let sender = test.test.sender.pubkey();
+    let before = test.test.balance(sender).await;
+
+    test.settle(&test.envelope(1, RentPayer::Sender))
+        .await
+        .unwrap();
+
+    let rent = test.minimum_balance(TokenAccount::LEN).await;
+    assert_eq!(test.test.balance(sender).await, before - rent);
+}
+
+#[tokio::test]
+async fn test_rent_payer_short_of_rent_is_retryable() {
+    let mut test = RecipientTest::start().await;
+    let sender = test.test.sender.pubkey();
+    let keep = 1_000_000;
+    let drain = test.test.balance(sender).await - keep;
+    test.test
+        .process(
+            &[system_instruction::transfer(
+                &sender,
+                &Pubkey::new_unique(),
+                drain,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let result = test.settle(&test.envelope(1, RentPayer::Sender)).await;
+
+    let error = assert_toss_error(result, 1, TossError::RentPayerInsufficientFunds);
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_created_account_must_be_associated_token_account() {
+    let mut test = RecipientTest::start().await;
+    let envelope = test.envelope(1, RentPayer::Relayer);
+
+    let result = test.settle_to(&envelope, Pubkey::new_unique()).await;
+
+    assert_toss_error(result, 1, TossError::InvalidAssociatedTokenAccount);
+}
+
+#[tokio::test]
+async fn test_sender_paying_rent_must_sign() {
+    let mut test = RecipientTest::start().await;
+    let envelope = test.envelope(1, RentPayer::Sender);
+    let mut instructions = test.test.settlement_instructions(&envelope);
+    instructions[1].accounts.splice(
+        8..8,
+        [
+            AccountMeta::new_readonly(test.mint, false),
+            AccountMeta::new(test.source, false),
+            AccountMeta::new(test.destination(), false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(spl_associated_token_account::ID, false),
+        ],
+    );
+
+    let result = test.test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::MissingRequiredSigner);
+}
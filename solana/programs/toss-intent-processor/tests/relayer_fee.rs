@@ -0,0 +1,93 @@
+mod common;
+
+use common::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, RelayerFee, SolanaIntentV2},
+    state::{NonceTracker, SettlementReceipt, Vault},
+};
+
+const FEE: u64 = 50_000;
+
+fn with_fee(test: &IntentTest, amount: u64, relayer: Option<Pubkey>) -> IntentEnvelope {
+    IntentEnvelope::V2(SolanaIntentV2 {
+        intent: intent(test.sender.pubkey(), Pubkey::new_unique(), amount),
+        extensions: vec![IntentExtension::RelayerFee(RelayerFee {
+            amount: FEE,
+            relayer,
+        })],
+    })
+}
+
+/// Settle `envelope` with `relayer`, funded first, signing and paying rent
+async fn settle_by(
+    test: &mut IntentTest,
+    envelope: &IntentEnvelope,
+    relayer: &Keypair,
+) -> Result<(), solana_program_test::BanksClientError> {
+    let payer = test.context.payer.pubkey();
+    let fund = system_instruction::transfer(&payer, &relayer.pubkey(), 1_000_000_000);
+    test.process(&[fund], &[]).await.unwrap();
+    let mut instructions = test.settlement_instructions(envelope);
+    instructions[1].accounts[6].pubkey = relayer.pubkey();
+    test.process(&instructions, &[relayer]).await
+}
+
+#[tokio::test]
+async fn test_relayer_fee_pays_the_submitter() {
+    let mut test = IntentTest::start().await;
+    let relayer = Keypair::new();
+    let envelope = with_fee(&test, 1_000_000, None);
+    let vault_before = test.balance(test.vault).await;
+
+    settle_by(&mut test, &envelope, &relayer).await.unwrap();
+
+    let rent = test.context.banks_client.get_rent().await.unwrap();
+    let rent_paid =
+        rent.minimum_balance(SettlementReceipt::LEN) + rent.minimum_balance(NonceTracker::LEN);
+    assert_eq!(test.balance(envelope.intent().to).await, 1_000_000);
+    assert_eq!(
+        test.balance(test.vault).await,
+        vault_before - 1_000_000 - FEE
+    );
+    assert_eq!(
+        test.balance(relayer.pubkey()).await,
+        1_000_000_000 - rent_paid + FEE
+    );
+}
+
+#[tokio::test]
+async fn test_fee_for_named_relayer_rejects_others() {
+    let mut test = IntentTest::start().await;
+    let named = Keypair::new();
+    let envelope = with_fee(&test, 1_000_000, Some(named.pubkey()));
+
+    let result = settle_by(&mut test, &envelope, &Keypair::new()).await;
+    assert_toss_error(result, 1, TossError::RelayerMismatch);
+
+    settle_by(&mut test, &envelope, &named).await.unwrap();
+    assert_eq!(test.balance(envelope.intent().to).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_vault_must_cover_amount_and_fee() {
+    let mut test = IntentTest::start().await;
+    let rent = test
+        .context
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(Vault::LEN);
+    let spendable = test.balance(test.vault).await - rent;
+    let envelope = with_fee(&test, spendable - FEE + 1, None);
+
+    let result = test.settle_envelope(&envelope, &[]).await;
+
+    assert_toss_error(result, 1, TossError::InsufficientFunds);
+}
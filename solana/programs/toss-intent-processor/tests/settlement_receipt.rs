@@ -0,0 +1,51 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{pubkey::Pubkey, signature::Signer, system_program};
+use toss_intent_processor::{error::TossError, intent_hash, state::SettlementReceipt};
+
+#[tokio::test]
+async fn test_settlement_creates_receipt() {
+    let mut test = IntentTest::start().await;
+    let mut intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    intent.nonce = 42;
+
+    test.settle(&intent).await.unwrap();
+
+    let hash = intent_hash(&pack(&intent));
+    let (address, bump) = SettlementReceipt::find_address(&hash, &test.program_id);
+    let account = test
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .expect("receipt exists");
+    assert_eq!(account.owner, test.program_id);
+
+    let receipt = SettlementReceipt::try_from_slice(&account.data).unwrap();
+    assert!(receipt.is_initialized);
+    assert_eq!(receipt.intent_hash, hash);
+    assert_eq!(receipt.sender, intent.from);
+    assert_eq!(receipt.recipient, intent.to);
+    assert_eq!(receipt.amount, intent.amount);
+    assert_eq!(receipt.mint, system_program::ID);
+    assert_eq!(receipt.nonce, 42);
+    assert_eq!(receipt.relayer, test.context.payer.pubkey());
+    assert_eq!(receipt.bump, bump);
+    assert!(receipt.slot > 0);
+}
+
+#[tokio::test]
+async fn test_resubmitted_intent_is_already_settled() {
+    let mut test = IntentTest::start().await;
+    let recipient = Pubkey::new_unique();
+    let intent = intent(test.sender.pubkey(), recipient, 1_000_000);
+
+    test.settle(&intent).await.unwrap();
+    let result = test.settle(&intent).await;
+
+    assert_toss_error(result, 1, TossError::IntentAlreadySettled);
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+}
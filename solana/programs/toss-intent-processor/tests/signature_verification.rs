@@ -0,0 +1,307 @@
+mod common;
+
+use common::*;
+use solana_program_test::{BanksClient, BanksClientError};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use toss_intent_processor::{
+    ed25519::{
+        self, Ed25519SignatureOffsets, CURRENT_INSTRUCTION, SIGNATURE_OFFSETS_SERIALIZED_SIZE,
+        SIGNATURE_OFFSETS_START,
+    },
+    error::TossError,
+    signing::{signing_preimage, Cluster, CLUSTER},
+};
+
+const AMOUNT: u64 = 1_000_000;
+
+struct Setup {
+    program_id: Pubkey,
+    sender: Keypair,
+    recipient: Pubkey,
+    relayer: Keypair,
+}
+
+async fn submit(
+    setup: &Setup,
+    instructions: &[Instruction],
+) -> (Result<(), BanksClientError>, BanksClient) {
+    let mut program_test = program_test(setup.program_id);
+    add_vault(
+        &mut program_test,
+        &setup.program_id,
+        &setup.sender.pubkey(),
+        SENDER_LAMPORTS,
+    );
+    fund(&mut program_test, &setup.relayer, SENDER_LAMPORTS);
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &[&payer, &setup.relayer],
+        recent_blockhash,
+    );
+    let result = banks_client.process_transaction(transaction).await;
+    (result, banks_client)
+}
+
+fn setup() -> Setup {
+    Setup {
+        program_id: Pubkey::new_unique(),
+        sender: Keypair::new(),
+        recipient: Pubkey::new_unique(),
+        relayer: Keypair::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_valid_signature_settles_intent() {
+    let setup = setup();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let message = preimage(&setup.program_id, &intent_data);
+    let signature = sign(&setup.sender, &message);
+
+    let (result, mut banks_client) = submit(
+        &setup,
+        &[
+            ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+            process_intent_ix(
+                setup.program_id,
+                &intent,
+                signature,
+                intent_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    result.unwrap();
+    assert_eq!(
+        banks_client.get_balance(setup.recipient).await.unwrap(),
+        AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_missing_ed25519_instruction_is_rejected() {
+    let setup = setup();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let message = preimage(&setup.program_id, &intent_data);
+    let signature = sign(&setup.sender, &message);
+
+    let (result, _) = submit(
+        &setup,
+        &[process_intent_ix(
+            setup.program_id,
+            &intent,
+            signature,
+            intent_data,
+            setup.relayer.pubkey(),
+        )],
+    )
+    .await;
+
+    assert_toss_error(result, 0, TossError::MissingSignatureInstruction);
+}
+
+#[tokio::test]
+async fn test_signature_by_other_key_is_rejected() {
+    let setup = setup();
+    let forger = Keypair::new();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let message = preimage(&setup.program_id, &intent_data);
+    let forged = sign(&forger, &message);
+
+    let (result, _) = submit(
+        &setup,
+        &[
+            ed25519::new_ed25519_instruction(&forger.pubkey(), &forged, &message),
+            process_intent_ix(
+                setup.program_id,
+                &intent,
+                forged,
+                intent_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    assert_toss_error(result, 1, TossError::SignerMismatch);
+}
+
+#[tokio::test]
+async fn test_supplied_signature_must_match_verified_signature() {
+    let setup = setup();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let message = preimage(&setup.program_id, &intent_data);
+    let signature = sign(&setup.sender, &message);
+
+    let (result, _) = submit(
+        &setup,
+        &[
+            ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+            process_intent_ix(
+                setup.program_id,
+                &intent,
+                [7u8; 64],
+                intent_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    assert_toss_error(result, 1, TossError::SignatureMismatch);
+}
+
+#[tokio::test]
+async fn test_signature_over_other_message_is_rejected() {
+    let setup = setup();
+    let signed_intent = intent(setup.sender.pubkey(), setup.recipient, 1);
+    let signed_data = preimage(&setup.program_id, &pack(&signed_intent));
+    let signature = sign(&setup.sender, &signed_data);
+
+    // Reuse the signature of a one-lamport intent for a larger payment
+    let forged_intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let forged_data = pack(&forged_intent);
+
+    let (result, _) = submit(
+        &setup,
+        &[
+            ed25519::new_ed25519_instruction(&signed_intent.from, &signature, &signed_data),
+            process_intent_ix(
+                setup.program_id,
+                &forged_intent,
+                signature,
+                forged_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    assert_toss_error(result, 1, TossError::MessageMismatch);
+}
+
+#[tokio::test]
+async fn test_multi_signature_ed25519_instruction_is_rejected() {
+    let setup = setup();
+    let other = Keypair::new();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let message = preimage(&setup.program_id, &intent_data);
+    let signature = sign(&setup.sender, &message);
+    let other_signature = sign(&other, &message);
+
+    // Two signatures over the same message, laid out as [pubkey, signature]
+    // pairs followed by the shared message
+    let first = SIGNATURE_OFFSETS_START + 2 * SIGNATURE_OFFSETS_SERIALIZED_SIZE;
+    let second = first + 96;
+    let message_offset = second + 96;
+    let offsets = |start: usize| Ed25519SignatureOffsets {
+        signature_offset: (start + 32) as u16,
+        signature_instruction_index: CURRENT_INSTRUCTION,
+        public_key_offset: start as u16,
+        public_key_instruction_index: CURRENT_INSTRUCTION,
+        message_data_offset: message_offset as u16,
+        message_data_size: message.len() as u16,
+        message_instruction_index: CURRENT_INSTRUCTION,
+    };
+    let mut data = vec![2, 0];
+    data.extend_from_slice(&offsets(first).pack());
+    data.extend_from_slice(&offsets(second).pack());
+    data.extend_from_slice(intent.from.as_ref());
+    data.extend_from_slice(&signature);
+    data.extend_from_slice(other.pubkey().as_ref());
+    data.extend_from_slice(&other_signature);
+    data.extend_from_slice(&message);
+
+    let multi_sig_ix = Instruction {
+        program_id: solana_sdk::ed25519_program::ID,
+        accounts: vec![],
+        data,
+    };
+
+    let (result, _) = submit(
+        &setup,
+        &[
+            multi_sig_ix,
+            process_intent_ix(
+                setup.program_id,
+                &intent,
+                signature,
+                intent_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    assert_toss_error(result, 1, TossError::MultipleSignatures);
+}
+
+#[tokio::test]
+async fn test_signature_over_raw_intent_bytes_is_rejected() {
+    let setup = setup();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+    let signature = sign(&setup.sender, &intent_data);
+
+    let (result, _) = submit(
+        &setup,
+        &[
+            ed25519::new_ed25519_instruction(&intent.from, &signature, &intent_data),
+            process_intent_ix(
+                setup.program_id,
+                &intent,
+                signature,
+                intent_data,
+                setup.relayer.pubkey(),
+            ),
+        ],
+    )
+    .await;
+
+    assert_toss_error(result, 1, TossError::MessageMismatch);
+}
+
+#[tokio::test]
+async fn test_signature_for_other_cluster_or_program_is_rejected() {
+    let setup = setup();
+    let intent = intent(setup.sender.pubkey(), setup.recipient, AMOUNT);
+    let intent_data = pack(&intent);
+
+    let other_cluster = signing_preimage(&setup.program_id, Cluster::Mainnet, &intent_data);
+    let other_program = signing_preimage(&Pubkey::new_unique(), CLUSTER, &intent_data);
+
+    for message in [other_cluster, other_program] {
+        let signature = sign(&setup.sender, &message);
+        let (result, _) = submit(
+            &setup,
+            &[
+                ed25519::new_ed25519_instruction(&intent.from, &signature, &message),
+                process_intent_ix(
+                    setup.program_id,
+                    &intent,
+                    signature,
+                    intent_data.clone(),
+                    setup.relayer.pubkey(),
+                ),
+            ],
+        )
+        .await;
+
+        assert_toss_error(result, 1, TossError::MessageMismatch);
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, signature::Signer};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2, SplitLeg},
+    intent_hash,
+    state::{SettlementReceipt, Vault},
+};
+
+fn split(test: &IntentTest, amount: u64, legs: &[(Pubkey, u64)]) -> IntentEnvelope {
+    IntentEnvelope::V2(SolanaIntentV2 {
+        intent: intent(test.sender.pubkey(), Pubkey::new_unique(), amount),
+        extensions: vec![IntentExtension::Split(
+            legs.iter()
+                .map(|&(recipient, amount)| SplitLeg { recipient, amount })
+                .collect(),
+        )],
+    })
+}
+
+fn leg_accounts(envelope: &IntentEnvelope) -> Vec<AccountMeta> {
+    envelope
+        .split_legs()
+        .iter()
+        .map(|leg| AccountMeta::new(leg.recipient, false))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_split_intent_pays_every_leg() {
+    let mut test = IntentTest::start().await;
+    let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let envelope = split(&test, 1_000_000, &[(first, 2_000_000), (second, 3_000_000)]);
+    let vault_before = test.balance(test.vault).await;
+
+    test.settle_with_accounts(&envelope, &leg_accounts(&envelope), &[])
+        .await
+        .unwrap();
+
+    assert_eq!(test.balance(envelope.intent().to).await, 1_000_000);
+    assert_eq!(test.balance(first).await, 2_000_000);
+    assert_eq!(test.balance(second).await, 3_000_000);
+    assert_eq!(test.balance(test.vault).await, vault_before - 6_000_000);
+
+    let address =
+        SettlementReceipt::find_address(&intent_hash(&envelope.pack()), &test.program_id).0;
+    let account = test
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    let receipt = SettlementReceipt::try_from_slice(&account.data).unwrap();
+    assert_eq!(receipt.amount, 6_000_000);
+}
+
+#[tokio::test]
+async fn test_split_recipients_must_match_leg_order() {
+    let mut test = IntentTest::start().await;
+    let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let envelope = split(&test, 1_000_000, &[(first, 2_000_000), (second, 3_000_000)]);
+    let mut accounts = leg_accounts(&envelope);
+    accounts.reverse();
+
+    let result = test.settle_with_accounts(&envelope, &accounts, &[]).await;
+
+    assert_toss_error(result, 1, TossError::RecipientMismatch);
+}
+
+#[tokio::test]
+async fn test_split_total_must_be_spendable() {
+    let mut test = IntentTest::start().await;
+    let rent = test
+        .context
+        .banks_client
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(Vault::LEN);
+    let spendable = test.balance(test.vault).await - rent;
+    // Each leg alone is affordable; together they are not
+    let envelope = split(&test, spendable, &[(Pubkey::new_unique(), 1)]);
+
+    let result = test
+        .settle_with_accounts(&envelope, &leg_accounts(&envelope), &[])
+        .await;
+
+    assert_toss_error(result, 1, TossError::InsufficientFunds);
+    assert_eq!(test.balance(envelope.intent().to).await, 0);
+}
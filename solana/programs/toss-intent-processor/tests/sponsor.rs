@@ -0,0 +1,87 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2},
+    state::Vault,
+    TossIntentInstruction,
+};
+
+/// Fund `sponsor` from the payer so it can pay rent on its own
+async fn fund_sponsor(test: &mut IntentTest, sponsor: &Keypair) {
+    let payer = test.context.payer.pubkey();
+    let fund = system_instruction::transfer(&payer, &sponsor.pubkey(), 1_000_000_000);
+    test.process(&[fund], &[]).await.unwrap();
+}
+
+fn sponsored(test: &IntentTest, sponsor: Pubkey) -> IntentEnvelope {
+    IntentEnvelope::V2(SolanaIntentV2 {
+        intent: intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000),
+        extensions: vec![IntentExtension::Sponsor(sponsor)],
+    })
+}
+
+#[tokio::test]
+async fn test_pinned_sponsor_funds_settlement() {
+    let mut test = IntentTest::start().await;
+    let sponsor = Keypair::new();
+    fund_sponsor(&mut test, &sponsor).await;
+    let envelope = sponsored(&test, sponsor.pubkey());
+    let mut instructions = test.settlement_instructions(&envelope);
+    instructions[1].accounts[6].pubkey = sponsor.pubkey();
+
+    // The payer submits and pays the transaction fee; the sponsor pays rent
+    test.process(&instructions, &[&sponsor]).await.unwrap();
+
+    assert_eq!(test.balance(envelope.intent().to).await, 1_000_000);
+    assert!(test.balance(sponsor.pubkey()).await < 1_000_000_000);
+}
+
+#[tokio::test]
+async fn test_other_relayer_cannot_settle_sponsored_intent() {
+    let mut test = IntentTest::start().await;
+    let envelope = sponsored(&test, Pubkey::new_unique());
+
+    let result = test.settle_envelope(&envelope, &[]).await;
+
+    assert_toss_error(result, 1, TossError::SponsorMismatch);
+}
+
+#[tokio::test]
+async fn test_sponsor_opens_vault_for_owner_without_sol() {
+    let mut test = IntentTest::start().await;
+    let sponsor = Keypair::new();
+    fund_sponsor(&mut test, &sponsor).await;
+    let owner = Pubkey::new_unique();
+    let vault = Vault::find_address(&owner, &test.program_id).0;
+    let deposit = Instruction {
+        program_id: test.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(owner, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new(sponsor.pubkey(), true),
+        ],
+        data: borsh::to_vec(&TossIntentInstruction::Deposit { amount: 0 }).unwrap(),
+    };
+
+    test.process(&[deposit], &[&sponsor]).await.unwrap();
+
+    assert_eq!(test.balance(owner).await, 0);
+    let account = test
+        .context
+        .banks_client
+        .get_account(vault)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(Vault::try_from_slice(&account.data).unwrap().owner, owner);
+}
@@ -0,0 +1,261 @@
+mod common;
+
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::AccountMeta, pubkey::Pubkey, signature::Signer, transaction::Transaction,
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeAmount},
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::Account as TokenAccount,
+};
+use toss_intent_processor::{
+    error::TossError,
+    intent::{
+        IntentEnvelope, IntentExtension, SolanaIntentV2, TokenTransfer, TransferFeeMode,
+        TransferFeeTerms,
+    },
+};
+
+const DECIMALS: u8 = 6;
+const SOURCE_BALANCE: u64 = 5_000_000;
+const FEE_BASIS_POINTS: u16 = 100;
+
+fn fee_schedule() -> TransferFee {
+    TransferFee {
+        epoch: 0.into(),
+        maximum_fee: u64::MAX.into(),
+        transfer_fee_basis_points: FEE_BASIS_POINTS.into(),
+    }
+}
+
+/// A bank with a Token-2022 mint, a sender token account holding
+/// `SOURCE_BALANCE` and an empty recipient token account
+struct Token2022Test {
+    test: IntentTest,
+    mint: Pubkey,
+    recipient: Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+    require_memo: bool,
+}
+
+impl Token2022Test {
+    async fn start(with_fee: bool, require_memo: bool) -> Self {
+        let mint = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let test = IntentTest::start_with(|program_test, vault| {
+            add_token_2022_mint(program_test, mint, DECIMALS, with_fee.then(fee_schedule));
+            add_token_2022_account(program_test, source, mint, *vault, SOURCE_BALANCE, false);
+            add_token_2022_account(program_test, destination, mint, recipient, 0, require_memo);
+        })
+        .await;
+        Self {
+            test,
+            mint,
+            recipient,
+            source,
+            destination,
+            require_memo,
+        }
+    }
+
+    fn envelope(&self, amount: u64, fee: Option<TransferFeeTerms>) -> IntentEnvelope {
+        let mut extensions = vec![IntentExtension::Token(TokenTransfer {
+            mint: self.mint,
+            decimals: DECIMALS,
+        })];
+        extensions.extend(fee.map(IntentExtension::TransferFee));
+        IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(self.test.sender.pubkey(), self.recipient, amount),
+            extensions,
+        })
+    }
+
+    fn token_accounts(&self) -> Vec<AccountMeta> {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(self.mint, false),
+            AccountMeta::new(self.source, false),
+            AccountMeta::new(self.destination, false),
+            AccountMeta::new_readonly(spl_token_2022::ID, false),
+        ];
+        if self.require_memo {
+            accounts.push(AccountMeta::new_readonly(spl_memo::ID, false));
+        }
+        accounts
+    }
+
+    async fn settle(&mut self, envelope: &IntentEnvelope) -> Result<(), BanksClientError> {
+        let accounts = self.token_accounts();
+        self.test
+            .settle_with_accounts(envelope, &accounts, &[])
+            .await
+    }
+
+    async fn token_balance(&mut self, address: Pubkey) -> u64 {
+        let account = self
+            .test
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        StateWithExtensions::<TokenAccount>::unpack(&account.data)
+            .unwrap()
+            .base
+            .amount
+    }
+
+    async fn withheld_fee(&mut self, address: Pubkey) -> u64 {
+        let account = self
+            .test
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        let state = StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap();
+        state
+            .get_extension::<TransferFeeAmount>()
+            .unwrap()
+            .withheld_amount
+            .into()
+    }
+}
+
+#[tokio::test]
+async fn test_token_2022_intent_settles() {
+    let mut test = Token2022Test::start(false, false).await;
+    let envelope = test.envelope(1_000_000, None);
+
+    test.settle(&envelope).await.unwrap();
+
+    assert_eq!(
+        test.token_balance(test.source).await,
+        SOURCE_BALANCE - 1_000_000
+    );
+    assert_eq!(test.token_balance(test.destination).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_gross_intent_recipient_bears_fee() {
+    let mut test = Token2022Test::start(true, false).await;
+    let fee = fee_schedule().calculate_fee(1_000_000).unwrap();
+    let envelope = test.envelope(
+        1_000_000,
+        Some(TransferFeeTerms {
+            mode: TransferFeeMode::Gross,
+            max_fee: fee,
+        }),
+    );
+
+    test.settle(&envelope).await.unwrap();
+
+    assert_eq!(
+        test.token_balance(test.source).await,
+        SOURCE_BALANCE - 1_000_000
+    );
+    assert_eq!(test.token_balance(test.destination).await, 1_000_000 - fee);
+    assert_eq!(test.withheld_fee(test.destination).await, fee);
+}
+
+#[tokio::test]
+async fn test_net_intent_delivers_exact_amount() {
+    let mut test = Token2022Test::start(true, false).await;
+    let fee = fee_schedule().calculate_inverse_fee(1_000_000).unwrap();
+    let envelope = test.envelope(
+        1_000_000,
+        Some(TransferFeeTerms {
+            mode: TransferFeeMode::Net,
+            max_fee: fee,
+        }),
+    );
+
+    test.settle(&envelope).await.unwrap();
+
+    assert_eq!(
+        test.token_balance(test.source).await,
+        SOURCE_BALANCE - 1_000_000 - fee
+    );
+    assert_eq!(test.token_balance(test.destination).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_undeclared_transfer_fee_is_rejected() {
+    let mut test = Token2022Test::start(true, false).await;
+    let envelope = test.envelope(1_000_000, None);
+
+    let result = test.settle(&envelope).await;
+
+    assert_toss_error(result, 1, TossError::TransferFeeNotAccepted);
+}
+
+#[tokio::test]
+async fn test_transfer_fee_above_maximum_is_rejected() {
+    let mut test = Token2022Test::start(true, false).await;
+    let fee = fee_schedule().calculate_fee(1_000_000).unwrap();
+    let envelope = test.envelope(
+        1_000_000,
+        Some(TransferFeeTerms {
+            mode: TransferFeeMode::Gross,
+            max_fee: fee - 1,
+        }),
+    );
+
+    let result = test.settle(&envelope).await;
+
+    assert_toss_error(result, 1, TossError::TransferFeeExceedsMax);
+}
+
+#[tokio::test]
+async fn test_memo_required_recipient_receives_memo() {
+    let mut test = Token2022Test::start(false, true).await;
+    let envelope = test.envelope(1_000_000, None);
+
+    test.settle(&envelope).await.unwrap();
+
+    assert_eq!(test.token_balance(test.destination).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_memo_required_recipient_receives_intent_memo() {
+    let mut test = Token2022Test::start(false, true).await;
+    let mut envelope = test.envelope(1_000_000, None);
+    let IntentEnvelope::V2(intent) = &mut envelope else {
+        unreachable!()
+    };
+    intent
+        .extensions
+        .push(IntentExtension::Memo("order #1042".into()));
+    let mut instructions = test.test.settlement_instructions(&envelope);
+    instructions[1].accounts.extend(test.token_accounts());
+    let blockhash = test.test.context.get_new_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&test.test.context.payer.pubkey()),
+        &[&test.test.context.payer],
+        blockhash,
+    );
+
+    let outcome = test
+        .test
+        .context
+        .banks_client
+        .process_transaction_with_metadata(transaction)
+        .await
+        .unwrap();
+
+    outcome.result.unwrap();
+    let logs = outcome.metadata.unwrap().log_messages;
+    assert!(logs
+        .iter()
+        .any(|log| log.ends_with("Memo (len 11): \"order #1042\"")));
+    assert_eq!(test.token_balance(test.destination).await, 1_000_000);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::{instruction::AccountMeta, program_pack::Pack, pubkey::Pubkey, signature::Signer};
+use spl_token::state::Account as TokenAccount;
+use toss_intent_processor::{
+    error::TossError,
+    intent::{IntentEnvelope, IntentExtension, SolanaIntentV2, TokenTransfer},
+    intent_hash,
+    state::SettlementReceipt,
+};
+
+const DECIMALS: u8 = 6;
+
+/// A bank with a mint, a vault token account holding `SOURCE_BALANCE` and
+/// an empty recipient token account owned by `destination_owner`
+struct TokenTest {
+    test: IntentTest,
+    mint: Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+}
+
+const SOURCE_BALANCE: u64 = 5_000_000;
+
+impl TokenTest {
+    async fn start(destination_owner: Pubkey) -> Self {
+        let mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let test = IntentTest::start_with(|program_test, vault| {
+            add_mint(program_test, mint, DECIMALS);
+            add_token_account(program_test, source, mint, *vault, SOURCE_BALANCE);
+            add_token_account(program_test, destination, mint, destination_owner, 0);
+        })
+        .await;
+        Self {
+            test,
+            mint,
+            source,
+            destination,
+        }
+    }
+
+    fn envelope(&self, recipient: Pubkey, amount: u64, token: TokenTransfer) -> IntentEnvelope {
+        IntentEnvelope::V2(SolanaIntentV2 {
+            intent: intent(self.test.sender.pubkey(), recipient, amount),
+            extensions: vec![IntentExtension::Token(token)],
+        })
+    }
+
+    fn token(&self) -> TokenTransfer {
+        TokenTransfer {
+            mint: self.mint,
+            decimals: DECIMALS,
+        }
+    }
+
+    async fn settle(&mut self, envelope: &IntentEnvelope) -> Result<(), BanksClientError> {
+        let accounts = [
+            AccountMeta::new_readonly(self.mint, false),
+            AccountMeta::new(self.source, false),
+            AccountMeta::new(self.destination, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+        ];
+        self.test
+            .settle_with_accounts(envelope, &accounts, &[])
+            .await
+    }
+
+    async fn token_balance(&mut self, address: Pubkey) -> u64 {
+        let account = self
+            .test
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        TokenAccount::unpack(&account.data).unwrap().amount
+    }
+}
+
+#[tokio::test]
+async fn test_token_intent_settles_with_transfer_checked() {
+    let recipient = Pubkey::new_unique();
+    let mut test = TokenTest::start(recipient).await;
+    let envelope = test.envelope(recipient, 1_250_000, test.token());
+
+    test.settle(&envelope).await.unwrap();
+
+    assert_eq!(
+        test.token_balance(test.source).await,
+        SOURCE_BALANCE - 1_250_000
+    );
+    assert_eq!(test.token_balance(test.destination).await, 1_250_000);
+
+    let hash = intent_hash(&envelope.pack());
+    let address = SettlementReceipt::find_address(&hash, &test.test.program_id).0;
+    let account = test
+        .test
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    let receipt = SettlementReceipt::try_from_slice(&account.data).unwrap();
+    assert_eq!(receipt.mint, test.mint);
+    assert_eq!(receipt.amount, 1_250_000);
+}
+
+#[tokio::test]
+async fn test_signed_decimals_must_match_mint() {
+    let recipient = Pubkey::new_unique();
+    let mut test = TokenTest::start(recipient).await;
+    let mut token = test.token();
+    token.decimals = 9;
+    let envelope = test.envelope(recipient, 1_000_000, token);
+
+    let result = test.settle(&envelope).await;
+
+    assert_toss_error(result, 1, TossError::TokenDecimalsMismatch);
+}
+
+#[tokio::test]
+async fn test_mint_account_must_match_intent() {
+    let recipient = Pubkey::new_unique();
+    let mut test = TokenTest::start(recipient).await;
+    let mut token = test.token();
+    token.mint = Pubkey::new_unique();
+    let envelope = test.envelope(recipient, 1_000_000, token);
+
+    let result = test.settle(&envelope).await;
+
+    assert_toss_error(result, 1, TossError::TokenMintMismatch);
+}
+
+#[tokio::test]
+async fn test_recipient_must_own_destination_token_account() {
+    let mut test = TokenTest::start(Pubkey::new_unique()).await;
+    let envelope = test.envelope(Pubkey::new_unique(), 1_000_000, test.token());
+
+    let result = test.settle(&envelope).await;
+
+    assert_toss_error(result, 1, TossError::TokenAccountOwnerMismatch);
+}
+
+#[tokio::test]
+async fn test_insufficient_token_balance_is_retryable() {
+    let recipient = Pubkey::new_unique();
+    let mut test = TokenTest::start(recipient).await;
+    let envelope = test.envelope(recipient, SOURCE_BALANCE + 1, test.token());
+
+    let result = test.settle(&envelope).await;
+
+    let error = assert_toss_error(result, 1, TossError::InsufficientFunds);
+    assert!(error.is_retryable());
+}
@@ -0,0 +1,289 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::*;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+};
+use spl_token::state::Account as TokenAccount;
+use toss_intent_processor::{
+    error::TossError,
+    intent::IntentEnvelope,
+    state::{Vault, DEFAULT_WITHDRAWAL_DELAY},
+    TossIntentInstruction,
+};
+
+fn vault_ix(
+    test: &IntentTest,
+    owner: &Pubkey,
+    instruction: TossIntentInstruction,
+    third: AccountMeta,
+) -> Instruction {
+    Instruction {
+        program_id: test.program_id,
+        accounts: vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(Vault::find_address(owner, &test.program_id).0, false),
+            third,
+        ],
+        data: borsh::to_vec(&instruction).unwrap(),
+    }
+}
+
+fn deposit_ix(test: &IntentTest, owner: &Pubkey, amount: u64) -> Instruction {
+    vault_ix(
+        test,
+        owner,
+        TossIntentInstruction::Deposit { amount },
+        AccountMeta::new_readonly(system_program::ID, false),
+    )
+}
+
+fn request_withdrawal_ix(
+    test: &IntentTest,
+    owner: &Pubkey,
+    mint: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut ix = vault_ix(
+        test,
+        owner,
+        TossIntentInstruction::RequestWithdrawal { mint, amount },
+        AccountMeta::new_readonly(system_program::ID, false),
+    );
+    ix.accounts.truncate(2);
+    ix
+}
+
+fn withdraw_ix(test: &IntentTest, owner: &Pubkey, destination: Pubkey) -> Instruction {
+    vault_ix(
+        test,
+        owner,
+        TossIntentInstruction::Withdraw,
+        AccountMeta::new(destination, false),
+    )
+}
+
+fn close_vault_ix(test: &IntentTest, owner: &Pubkey, destination: Pubkey) -> Instruction {
+    vault_ix(
+        test,
+        owner,
+        TossIntentInstruction::CloseVault,
+        AccountMeta::new(destination, false),
+    )
+}
+
+/// Request a withdrawal as the sender and wait out the delay
+async fn matured_withdrawal(test: &mut IntentTest, mint: Pubkey, amount: u64) {
+    let ix = request_withdrawal_ix(test, &test.sender.pubkey(), mint, amount);
+    test.process(&[ix], &[]).await.unwrap();
+    test.advance_clock(DEFAULT_WITHDRAWAL_DELAY as i64).await;
+}
+
+async fn vault_rent(test: &mut IntentTest) -> u64 {
+    let rent = test.context.banks_client.get_rent().await.unwrap();
+    rent.minimum_balance(Vault::LEN)
+}
+
+#[tokio::test]
+async fn test_first_deposit_creates_vault() {
+    let mut test = IntentTest::start().await;
+    let owner = Keypair::new();
+    let vault = Vault::find_address(&owner.pubkey(), &test.program_id).0;
+    let payer = test.context.payer.pubkey();
+    let fund_owner = system_instruction::transfer(&payer, &owner.pubkey(), 1_000_000_000);
+    test.process(&[fund_owner], &[]).await.unwrap();
+
+    let ix = deposit_ix(&test, &owner.pubkey(), 500_000_000);
+    test.process(&[ix], &[&owner]).await.unwrap();
+
+    let rent = vault_rent(&mut test).await;
+    assert_eq!(test.balance(vault).await, rent + 500_000_000);
+    let account = test
+        .context
+        .banks_client
+        .get_account(vault)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.owner, test.program_id);
+    let state = Vault::try_from_slice(&account.data).unwrap();
+    assert_eq!(state.owner, owner.pubkey());
+}
+
+#[tokio::test]
+async fn test_deposit_tops_up_existing_vault() {
+    let mut test = IntentTest::start().await;
+    let sender = test.sender.pubkey();
+    let before = test.balance(test.vault).await;
+
+    let ix = deposit_ix(&test, &sender, 1_000_000);
+    test.process(&[ix], &[]).await.unwrap();
+
+    assert_eq!(test.balance(test.vault).await, before + 1_000_000);
+}
+
+#[tokio::test]
+async fn test_intent_settles_from_vault_without_sender_signature() {
+    let mut test = IntentTest::start().await;
+    let recipient = Pubkey::new_unique();
+    let sender_before = test.balance(test.sender.pubkey()).await;
+    let vault_before = test.balance(test.vault).await;
+
+    let intent = intent(test.sender.pubkey(), recipient, 1_000_000);
+    let instructions = test.settlement_instructions(&IntentEnvelope::V1(intent.clone()));
+    assert!(!instructions[1].accounts[0].is_signer);
+    test.process(&instructions, &[]).await.unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+    assert_eq!(test.balance(test.vault).await, vault_before - 1_000_000);
+    assert_eq!(test.balance(test.sender.pubkey()).await, sender_before);
+}
+
+#[tokio::test]
+async fn test_intent_cannot_spend_vault_rent() {
+    let mut test = IntentTest::start().await;
+    let recipient = Pubkey::new_unique();
+    let spendable = test.balance(test.vault).await - vault_rent(&mut test).await;
+
+    let result = test
+        .settle(&intent(test.sender.pubkey(), recipient, spendable + 1))
+        .await;
+
+    let error = assert_toss_error(result, 1, TossError::InsufficientFunds);
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_intent_requires_sender_vault() {
+    let mut test = IntentTest::start().await;
+    let intent = intent(test.sender.pubkey(), Pubkey::new_unique(), 1_000_000);
+    let mut instructions = test.settlement_instructions(&IntentEnvelope::V1(intent.clone()));
+    instructions[1].accounts[7] = AccountMeta::new(Pubkey::new_unique(), false);
+
+    let result = test.process(&instructions, &[]).await;
+
+    assert_toss_error(result, 1, TossError::InvalidVault);
+}
+
+#[tokio::test]
+async fn test_withdrawal_waits_for_delay() {
+    let mut test = IntentTest::start().await;
+    let sender = test.sender.pubkey();
+    let destination = Pubkey::new_unique();
+
+    let ix = withdraw_ix(&test, &sender, destination);
+    let result = test.process(&[ix], &[]).await;
+    assert_toss_error(result, 0, TossError::WithdrawalNotRequested);
+
+    let ix = request_withdrawal_ix(&test, &sender, system_program::ID, 1_000_000);
+    test.process(&[ix], &[]).await.unwrap();
+    test.advance_clock(DEFAULT_WITHDRAWAL_DELAY as i64 - 60)
+        .await;
+    let ix = withdraw_ix(&test, &sender, destination);
+    let result = test.process(&[ix], &[]).await;
+    let error = assert_toss_error(result, 0, TossError::WithdrawalLocked);
+    assert!(error.is_retryable());
+
+    test.advance_clock(60).await;
+    let ix = withdraw_ix(&test, &sender, destination);
+    test.process(&[ix], &[]).await.unwrap();
+    assert_eq!(test.balance(destination).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn test_intents_settle_while_withdrawal_is_pending() {
+    let mut test = IntentTest::start().await;
+    let sender = test.sender.pubkey();
+    let recipient = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let spendable = test.balance(test.vault).await - vault_rent(&mut test).await;
+
+    let ix = request_withdrawal_ix(&test, &sender, system_program::ID, spendable);
+    test.process(&[ix], &[]).await.unwrap();
+    test.settle(&intent(sender, recipient, 1_000_000))
+        .await
+        .unwrap();
+    test.advance_clock(DEFAULT_WITHDRAWAL_DELAY as i64).await;
+    let ix = withdraw_ix(&test, &sender, destination);
+    test.process(&[ix], &[]).await.unwrap();
+
+    assert_eq!(test.balance(recipient).await, 1_000_000);
+    assert_eq!(test.balance(destination).await, spendable - 1_000_000);
+}
+
+#[tokio::test]
+async fn test_only_owner_can_request_withdrawal() {
+    let mut test = IntentTest::start().await;
+    let thief = Keypair::new();
+    let mut ix = request_withdrawal_ix(&test, &thief.pubkey(), system_program::ID, 1_000_000);
+    ix.accounts[1].pubkey = test.vault;
+
+    let result = test.process(&[ix], &[&thief]).await;
+
+    assert_toss_error(result, 0, TossError::VaultOwnerMismatch);
+}
+
+#[tokio::test]
+async fn test_owner_withdraws_vault_tokens() {
+    let mint = Pubkey::new_unique();
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let mut test = IntentTest::start_with(|program_test, vault| {
+        add_mint(program_test, mint, 6);
+        add_token_account(program_test, source, mint, *vault, 5_000_000);
+        add_token_account(program_test, destination, mint, Pubkey::new_unique(), 0);
+    })
+    .await;
+    matured_withdrawal(&mut test, mint, 2_000_000).await;
+    let ix = Instruction {
+        program_id: test.program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(test.sender.pubkey(), true),
+            AccountMeta::new(test.vault, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+        ],
+        data: borsh::to_vec(&TossIntentInstruction::WithdrawTokens { decimals: 6 }).unwrap(),
+    };
+
+    test.process(&[ix], &[]).await.unwrap();
+
+    let account = test
+        .context
+        .banks_client
+        .get_account(destination)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TokenAccount::unpack(&account.data).unwrap().amount,
+        2_000_000
+    );
+}
+
+#[tokio::test]
+async fn test_closed_vault_returns_all_lamports() {
+    let mut test = IntentTest::start().await;
+    let sender = test.sender.pubkey();
+    let destination = Pubkey::new_unique();
+    let vault_balance = test.balance(test.vault).await;
+
+    let ix = close_vault_ix(&test, &sender, destination);
+    let result = test.process(&[ix], &[]).await;
+    assert_toss_error(result, 0, TossError::WithdrawalNotRequested);
+
+    matured_withdrawal(&mut test, system_program::ID, 1).await;
+    let ix = close_vault_ix(&test, &sender, destination);
+    test.process(&[ix], &[]).await.unwrap();
+
+    assert_eq!(test.balance(destination).await, vault_balance);
+    let result = test.settle(&intent(sender, Pubkey::new_unique(), 1)).await;
+    assert_toss_error(result, 1, TossError::InvalidVault);
+}